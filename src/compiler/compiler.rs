@@ -1,8 +1,13 @@
 use super::{generator::generate, parser::parse};
-pub fn compile(input: String) -> Vec<String> {
+// コード生成の設定。コマンドラインのフラグから決まる
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub bounds_check: bool,       // 配列の添字が範囲内か実行時に検査する
+    pub warn_uninitialized: bool, // 初期化子の無いローカル変数を代入前に読んでいたら警告する
+}
+pub fn compile(input: String, opts: &Options) -> Vec<String> {
     let parsed = parse(&input);
-    if parsed.is_err() {
-        let err_msg = parsed.unwrap_err();
+    if let Err(err_msg) = parsed {
         panic!(
             "{}\nat line {}\n    {}\n{}^ something wrong here",
             err_msg.reason,
@@ -11,8 +16,8 @@ pub fn compile(input: String) -> Vec<String> {
             "    ".to_string() + &" ".repeat(err_msg.index)
         )
     }
-    match generate(&parsed.unwrap()) {
+    match generate(&parsed.unwrap(), &input, opts) {
         Err(e) => panic!("{}", e.join("\n")),
-        Ok(e) => return e,
+        Ok(e) => e,
     }
 }
@@ -5,21 +5,92 @@ pub const RETURN: &str = "return";
 pub const IF: &str = "if";
 pub const WHILE: &str = "while";
 pub const FOR: &str = "for";
+pub const GOTO: &str = "goto";
 pub const INT: &str = "int";
-pub const TYPES: [&str; 1] = [INT];
+pub const CHAR: &str = "char";
+pub const SHORT: &str = "short";
+pub const LONG: &str = "long";
+pub const SIGNED: &str = "signed";
+pub const UNSIGNED: &str = "unsigned";
+pub const FLOAT: &str = "float";
+pub const DOUBLE: &str = "double";
+pub const VOID: &str = "void";
+pub const STATIC: &str = "static";
+pub const EXTERN: &str = "extern";
+pub const TYPEDEF: &str = "typedef";
+pub const SIZEOF: &str = "sizeof";
+pub const ALIGNOF: &str = "_Alignof";
+pub const STATIC_ASSERT: &str = "_Static_assert";
+pub const STRUCT: &str = "struct";
+pub const UNION: &str = "union";
+pub const ENUM: &str = "enum";
+pub const CONST: &str = "const";
+pub const VOLATILE: &str = "volatile";
+pub const ASM: [&str; 2] = ["asm", "__asm__"];
+pub const ASM_VOLATILE: &str = "__volatile__";
+pub const TYPES: [&str; 13] = [
+    INT, CHAR, SHORT, LONG, SIGNED, UNSIGNED, FLOAT, DOUBLE, STRUCT, UNION, ENUM, CONST, VOLATILE,
+];
 pub const BLOCK_EXPECTED: &str = "block begin { expected";
 pub const BRACE_NOT_BALANCED: &str = "brace{} not balanced";
 pub const TYPE_WANTED: &str = "type declaration required";
 pub const IDENTITY_WANTED: &str = "identity wanted";
 pub const LEFT_VALUE_IS_NOT_ASSIGNABLE: &str = "left value is not assignable";
 pub const NOT_AVAILABLE_FOR_ARRAY_INDEX: &str = "this type is not available for array index";
+// Intelの記法では演算子やレジスタの名前と同じシンボルをオペランドに書けない
+pub const ASM_RESERVED: [&str; 33] = [
+    "and", "or", "xor", "not", "shl", "shr", "mod", "eq", "ne", "lt", "le", "gt", "ge", "offset",
+    "ptr", "short", "flat", "byte", "word", "dword", "fword", "qword", "tbyte", "oword", "xmmword",
+    "ymmword", "zmmword", "st", "cs", "ds", "es", "fs", "gs",
+];
+pub fn is_asm_reserved(name: &str) -> bool {
+    if ASM_RESERVED.contains(&name)
+        || [
+            "ss", "ip", "eip", "rip", "sp", "bp", "si", "di", "spl", "bpl", "sil", "dil",
+        ]
+        .contains(&name)
+    {
+        return true;
+    }
+    let legacy = ["a", "b", "c", "d"];
+    if name.len() == 2 && legacy.contains(&&name[..1]) && ["l", "h", "x"].contains(&&name[1..]) {
+        return true;
+    }
+    if (name.starts_with('e') || name.starts_with('r'))
+        && (["ax", "bx", "cx", "dx", "sp", "bp", "si", "di"].contains(&&name[1..]))
+    {
+        return true;
+    }
+    // r8からr15、xmm0などは数字で終わる
+    let prefixes = ["r", "xmm", "ymm", "zmm", "mm", "cr", "dr", "k"];
+    prefixes.iter().any(|p| {
+        name.strip_prefix(p).is_some_and(|rest| {
+            let digits = rest.trim_end_matches(['b', 'w', 'd']);
+            !digits.is_empty()
+                && digits.chars().all(|c| c.is_ascii_digit())
+                && (*p == "r" || rest == digits)
+        })
+    })
+}
 pub fn size_directive(t: &Type) -> String {
     match t.sizeof() {
+        1 => "BYTE PTR ",
+        2 => "WORD PTR ",
         4 => "DWORD PTR ",
-        8 | _ => "",
+        _ => "",
+    }
+    .into()
+}
+pub fn data_directive(size: usize) -> String {
+    match size {
+        1 => ".byte",
+        2 => ".short",
+        4 => ".long",
+        _ => ".quad",
     }
     .into()
 }
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Register {
     _Ax,
     Di,
@@ -28,10 +99,31 @@ pub enum Register {
     Cx,
     _8,
     _9,
+    _11,
 }
 
 pub fn register(size: usize, r: &Register) -> String {
     match size {
+        1 => match r {
+            Register::_Ax => "al",
+            Register::Di => "dil",
+            Register::Si => "sil",
+            Register::Dx => "dl",
+            Register::Cx => "cl",
+            Register::_8 => "r8b",
+            Register::_9 => "r9b",
+            Register::_11 => "r11b",
+        },
+        2 => match r {
+            Register::_Ax => "ax",
+            Register::Di => "di",
+            Register::Si => "si",
+            Register::Dx => "dx",
+            Register::Cx => "cx",
+            Register::_8 => "r8w",
+            Register::_9 => "r9w",
+            Register::_11 => "r11w",
+        },
         4 => match r {
             Register::_Ax => "eax",
             Register::Di => "edi",
@@ -40,8 +132,9 @@ pub fn register(size: usize, r: &Register) -> String {
             Register::Cx => "ecx",
             Register::_8 => "r8d",
             Register::_9 => "r9d",
+            Register::_11 => "r11d",
         },
-        8 | _ => match r {
+        _ => match r {
             Register::_Ax => "rax",
             Register::Di => "rdi",
             Register::Si => "rsi",
@@ -49,6 +142,7 @@ pub fn register(size: usize, r: &Register) -> String {
             Register::Cx => "rcx",
             Register::_8 => "r8",
             Register::_9 => "r9",
+            Register::_11 => "r11",
         },
     }
     .into()
@@ -0,0 +1,391 @@
+use std::cmp::Ordering;
+
+use super::ldouble::LDouble;
+use super::node::{
+    Add, AddSub, Assign, Compare, Equality, Equals, Expr, Lvar, Mul, MulDiv, Primary, PrimaryNode,
+    PtrOpe, Relational, Typed, Unary,
+};
+use super::type_::Type;
+
+// 定数式の値。整数は式の型の幅に収めた値、floatはfloatの精度に丸めた値を持つ
+#[derive(Clone, Copy)]
+enum Const {
+    Int(i64),
+    Float(f64),
+    LDouble(LDouble), // long doubleは80bitの精度で計算する
+}
+impl Const {
+    fn int(self) -> Option<i64> {
+        match self {
+            Const::Int(v) => Some(v),
+            _ => None,
+        }
+    }
+    // 整数は型の符号に従って変換する
+    fn float(self, t: &Type) -> f64 {
+        match self {
+            Const::Int(v) if t.is_signed() => v as f64,
+            Const::Int(v) => v as u64 as f64,
+            Const::Float(f) => f,
+            Const::LDouble(l) => l.to_f64(),
+        }
+    }
+    fn ldouble(self, t: &Type) -> LDouble {
+        match self {
+            Const::Int(v) if t.is_signed() => LDouble::from_i64(v),
+            Const::Int(v) => LDouble::from_u64(v as u64),
+            Const::Float(f) => LDouble::from_f64(f),
+            Const::LDouble(l) => l,
+        }
+    }
+    // 型tの値を浮動小数点数型toへ変換する
+    fn to_float(self, t: &Type, to: &Type) -> Const {
+        match (to.base(), self) {
+            (Type::LongDouble, _) => Const::LDouble(self.ldouble(t)),
+            // doubleを経由すると2度丸めることになるので、floatへ直接丸める
+            (Type::Float, Const::LDouble(l)) => Const::Float(l.to_f32() as f64),
+            (Type::Float, _) => Const::Float(self.float(t) as f32 as f64),
+            _ => Const::Float(self.float(t)),
+        }
+    }
+    fn negate(self, t: &Type) -> Const {
+        match self {
+            Const::Int(v) => Const::Int(t.promote().convert(v.wrapping_neg())),
+            Const::Float(f) => Const::Float(-f),
+            Const::LDouble(l) => Const::LDouble(-l),
+        }
+    }
+    // 整数型tへ変換する。浮動小数点数の小数部は0に向かって切り捨て、範囲外なら飽和させる
+    fn truncate(self, t: &Type) -> i64 {
+        let v = match self {
+            Const::Int(v) => return t.convert(v),
+            Const::Float(f) => f as i128,
+            Const::LDouble(l) => l.trunc(),
+        };
+        t.convert(if t.is_signed() {
+            v.clamp(i64::MIN as i128, i64::MAX as i128) as i64
+        } else {
+            v.clamp(0, u64::MAX as i128) as u64 as i64
+        })
+    }
+}
+// 定数式の評価。コンパイル時に値が決まらなければNoneを返す
+trait Fold {
+    fn fold(&self) -> Option<Const>;
+}
+// 左辺と右辺を通常の算術型変換で揃えて計算する。整数なら結果を型の幅に収める
+fn binary(
+    l: Const,
+    lt: &Type,
+    r: &Typed<impl Fold>,
+    int: impl Fn(i64, i64, &Type) -> Option<i64>,
+    float: impl Fn(f64, f64) -> f64,
+    ldouble: impl Fn(LDouble, LDouble) -> LDouble,
+) -> Option<(Const, Type)> {
+    let t = lt.common(&r.1);
+    let rv = r.0.fold()?;
+    let value = if t.is_integer() {
+        Const::Int(t.convert(int(t.convert(l.int()?), t.convert(rv.int()?), &t)?))
+    } else if t == Type::LongDouble {
+        Const::LDouble(ldouble(l.ldouble(lt), rv.ldouble(&r.1)))
+    } else if t.is_float() {
+        Const::Float(float(l.float(lt), rv.float(&r.1))).to_float(&t, &t)
+    } else {
+        return None;
+    };
+    Some((value, t))
+}
+// 比較の結果。NaNとの比較はNone
+fn compare(l: Const, lt: &Type, r: &Typed<impl Fold>) -> Option<Option<Ordering>> {
+    let t = lt.common(&r.1);
+    let rv = r.0.fold()?;
+    if t == Type::LongDouble {
+        return Some(l.ldouble(lt).partial_cmp(&rv.ldouble(&r.1)));
+    }
+    if t.is_float() {
+        return Some(l.float(lt).partial_cmp(&rv.float(&r.1)));
+    }
+    if !t.is_integer() {
+        return None;
+    }
+    let (l, r) = (t.convert(l.int()?), t.convert(rv.int()?));
+    // 符号なしなら符号なしとして比べる
+    Some(Some(if t.is_signed() {
+        l.cmp(&r)
+    } else {
+        (l as u64).cmp(&(r as u64))
+    }))
+}
+impl Expr {
+    pub fn eval(&self) -> Option<i64> {
+        self.fold()?.int()
+    }
+}
+impl Fold for Expr {
+    fn fold(&self) -> Option<Const> {
+        match self {
+            Expr::Asgn(e) if !e.ret => e.assign.fold(),
+            _ => None,
+        }
+    }
+}
+impl Fold for Assign {
+    fn fold(&self) -> Option<Const> {
+        match self {
+            Assign::Rv(r) => r.eq.0.fold(),
+            Assign::Asgn(_) => None,
+        }
+    }
+}
+impl Assign {
+    pub fn eval(&self) -> Option<i64> {
+        self.fold()?.int()
+    }
+    // floatかdoubleの定数。整数の定数式も変換し、型tの精度に丸める
+    pub fn eval_float(&self, t: &Type) -> Option<f64> {
+        match self.fold()?.to_float(&self.type_(), t) {
+            Const::Float(f) => Some(f),
+            _ => None,
+        }
+    }
+    // 整数型の初期値。浮動小数点数の定数式なら切り捨てる
+    pub fn eval_as(&self, t: &Type) -> Option<i64> {
+        Some(self.fold()?.truncate(t))
+    }
+    // long doubleの定数。整数やdoubleの定数式も80bitの値にする
+    pub fn eval_ldouble(&self) -> Option<LDouble> {
+        Some(self.fold()?.ldouble(&self.type_()))
+    }
+}
+impl Equality {
+    pub fn eval(&self) -> Option<i64> {
+        self.fold()?.int()
+    }
+}
+impl Fold for Equality {
+    fn fold(&self) -> Option<Const> {
+        let (mut value, mut t) = (self.first.0.fold()?, self.first.1.clone());
+        for rel in self.relationals.iter() {
+            let ord = compare(value, &t, rel)?;
+            value = Const::Int(match rel.0.ope.as_ref()? {
+                Equals::Equal => ord == Some(Ordering::Equal),
+                Equals::NotEqual => ord != Some(Ordering::Equal),
+            } as i64);
+            t = Type::Int;
+        }
+        Some(value)
+    }
+}
+impl Fold for Relational {
+    fn fold(&self) -> Option<Const> {
+        let (mut value, mut t) = (self.first.0.fold()?, self.first.1.clone());
+        for add in self.adds.iter() {
+            let ord = compare(value, &t, add)?;
+            value = Const::Int(match add.0.ope.as_ref()? {
+                Compare::Lt => ord == Some(Ordering::Less),
+                Compare::Lte => matches!(ord, Some(Ordering::Less | Ordering::Equal)),
+                Compare::Gt => ord == Some(Ordering::Greater),
+                Compare::Gte => matches!(ord, Some(Ordering::Greater | Ordering::Equal)),
+            } as i64);
+            t = Type::Int;
+        }
+        Some(value)
+    }
+}
+impl Fold for Add {
+    fn fold(&self) -> Option<Const> {
+        let (mut value, mut t) = (self.first.0.fold()?, self.first.1.clone());
+        for mul in self.muls.iter() {
+            (value, t) = match mul.0.ope.as_ref()? {
+                AddSub::Plus => binary(
+                    value,
+                    &t,
+                    mul,
+                    |l, r, _| Some(l.wrapping_add(r)),
+                    |l, r| l + r,
+                    |l, r| l + r,
+                )?,
+                AddSub::Minus => binary(
+                    value,
+                    &t,
+                    mul,
+                    |l, r, _| Some(l.wrapping_sub(r)),
+                    |l, r| l - r,
+                    |l, r| l - r,
+                )?,
+            };
+        }
+        Some(value)
+    }
+}
+impl Fold for Mul {
+    fn fold(&self) -> Option<Const> {
+        let (mut value, mut t) = (self.first.0.fold()?, self.first.1.clone());
+        for unary in self.unarys.iter() {
+            (value, t) = match unary.0.ope().as_ref()? {
+                MulDiv::Multi => binary(
+                    value,
+                    &t,
+                    unary,
+                    |l, r, _| Some(l.wrapping_mul(r)),
+                    |l, r| l * r,
+                    |l, r| l * r,
+                )?,
+                MulDiv::Divide => binary(
+                    value,
+                    &t,
+                    unary,
+                    // 符号なしなら符号なしとして割る
+                    |l, r, t| match t.is_signed() {
+                        true => l.checked_div(r),
+                        false => (l as u64).checked_div(r as u64).map(|v| v as i64),
+                    },
+                    |l, r| l / r,
+                    |l, r| l / r,
+                )?,
+            };
+        }
+        Some(value)
+    }
+}
+impl Fold for Unary {
+    fn fold(&self) -> Option<Const> {
+        match self {
+            Unary::Var(v) if v._arrs.is_empty() => v.prim.0.fold(),
+            Unary::Cast(c) if c.to.is_arithmetic() => {
+                let value = c.unary.0.fold()?;
+                let value = match value {
+                    _ if c.to.is_float() => value.to_float(&c.unary.1, &c.to),
+                    Const::Int(v) => Const::Int(c.to.convert(v)),
+                    f => Const::Int(f.truncate(&c.to)),
+                };
+                match c.sign {
+                    Some(AddSub::Minus) => Some(value.negate(&c.to)),
+                    _ => Some(value),
+                }
+            }
+            _ => None,
+        }
+    }
+}
+impl Fold for Primary {
+    fn fold(&self) -> Option<Const> {
+        let value = match &self.node.0 {
+            PrimaryNode::Num((n, _)) => Const::Int(*n),
+            PrimaryNode::Float(f) => Const::Float(*f),
+            PrimaryNode::LDouble(s) => Const::LDouble(LDouble::parse(s)?),
+            PrimaryNode::Expr(e) => e.fold()?,
+            _ => return None,
+        };
+        // 単項の-は整数拡張した型で計算する
+        match self.ope {
+            Some(AddSub::Minus) => Some(value.negate(&self.node.1)),
+            _ => Some(value),
+        }
+    }
+}
+// アドレス定数。シンボルのラベルと、そこからのバイト単位のオフセットを返す
+type Addr = (String, i64);
+impl Expr {
+    fn eval_addr(&self) -> Option<Addr> {
+        match self {
+            Expr::Asgn(e) if !e.ret => e.assign.eval_addr(),
+            _ => None,
+        }
+    }
+}
+impl Assign {
+    pub fn eval_addr(&self) -> Option<Addr> {
+        let Assign::Rv(r) = self else {
+            return None;
+        };
+        let eq = &r.eq.0;
+        if !eq.relationals.is_empty() || !eq.first.0.adds.is_empty() {
+            return None;
+        }
+        eq.first.0.first.0.eval_addr()
+    }
+}
+impl Add {
+    // アドレスの側を起点に、整数の側は指す先の大きさを掛けて足す
+    fn eval_addr(&self) -> Option<Addr> {
+        let terms = std::iter::once((&self.first, true)).chain(
+            self.muls
+                .iter()
+                .map(|m| (m, matches!(m.0.ope, Some(AddSub::Plus)))),
+        );
+        let (mut base, mut stride, mut count) = (None, 0, 0);
+        for (m, plus) in terms {
+            if m.1.is_integer() {
+                let v = m.0.fold()?.int()?;
+                count += if plus { v } else { -v };
+            } else if base.is_none() && plus && m.0.unarys.is_empty() {
+                base = Some(value_addr(&m.0.first)?);
+                stride = m.1.deref()?.sizeof() as i64;
+            } else {
+                return None;
+            }
+        }
+        let (label, offset) = base?;
+        Some((label, offset + count * stride))
+    }
+}
+// 値がアドレス定数になる式
+fn value_addr(u: &Typed<Unary>) -> Option<Addr> {
+    match &u.0 {
+        // 関数名や配列は先頭のアドレスになる
+        _ if matches!(u.1.base(), Type::Func(_) | Type::Array(_)) => object_addr(&u.0),
+        Unary::Ptr(p) if matches!(p.ope, PtrOpe::Deref) => object_addr(&p.unary.0),
+        Unary::Cast(c) if c.sign.is_none() && (c.to.is_pointer() || c.to.sizeof() == 8) => {
+            value_addr(&c.unary)
+        }
+        Unary::Var(v) if v._arrs.is_empty() && v.prim.0.ope.is_none() => match &v.prim.0.node.0 {
+            PrimaryNode::Expr(e) => e.eval_addr(),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+// 大域変数や関数、その要素やメンバの位置
+fn object_addr(u: &Unary) -> Option<Addr> {
+    match u {
+        Unary::Var(v) if v.prim.0.ope.is_none() => {
+            let base = match &v.prim.0.node.0 {
+                PrimaryNode::Lv(Lvar::Global(i)) => (i.name.clone(), 0),
+                PrimaryNode::Expr(e) => match e.as_ref() {
+                    Expr::Asgn(a) if !a.ret => object_addr(a.assign.unary()?)?,
+                    _ => return None,
+                },
+                _ => return None,
+            };
+            index(base, &v.prim.1, &v._arrs)
+        }
+        Unary::Member(m) if m.sign.is_none() => {
+            let (label, offset) = if m.arrow {
+                value_addr(&m.unary)?
+            } else {
+                object_addr(&m.unary.0)?
+            };
+            index(
+                (label, offset + m.member.offset as i64),
+                &m.member.type_,
+                &m._arrs,
+            )
+        }
+        Unary::Ptr(p) if matches!(p.ope, PtrOpe::Ref) => value_addr(&p.unary),
+        _ => None,
+    }
+}
+// 配列の定数の添字は位置に足せる。ポインタの添字は指す先を読むので定数にならない
+fn index((label, mut offset): Addr, t: &Type, arrs: &[Typed<Expr>]) -> Option<Addr> {
+    let mut t = t.clone();
+    for a in arrs {
+        let Type::Array(arr) = t.base() else {
+            return None;
+        };
+        let item = arr.0.clone();
+        offset += a.0.eval()? * item.sizeof() as i64;
+        t = item;
+    }
+    Some((label, offset))
+}
@@ -1,29 +1,29 @@
-use std::{cmp, collections::HashMap};
+use std::collections::{BTreeSet, HashMap, HashSet};
 
-use crate::compiler::consts::{IDENTITY_OFFSET, Register, register};
+use crate::compiler::consts::{
+    IDENTITY_OFFSET, Register, data_directive, is_asm_reserved, register,
+};
+use crate::compiler::type_::align_to;
 
+use super::compiler::Options;
 use super::{
     consts::{LEFT_VALUE_IS_NOT_ASSIGNABLE, size_directive},
     node::{
-        Add, AddSub, Assign, Block, Compare, Equality, Equals, Expr, Fcall, Fdef, For, If, Lvar,
-        Mul, MulDiv, Primary, PrimaryNode, Program, PtrOpe, Relational, Statement, Typed, Unary,
-        VarDef, While,
+        Add, AddSub, Asm, AsmOperand, Assign, Block, Compare, Equality, Equals, Expr, Fcall, Fdef,
+        For, GInit, GVar, If, InitElem, InitValue, Initializer, Linkage, Lvar, Mul, MulDiv,
+        Primary, PrimaryNode, Program, PtrOpe, Relational, Statement, Typed, Unary, UnaryCall,
+        UnaryCast, UnaryVar, VarDef, While,
     },
-    type_::Type,
+    type_::{BitField, Type},
 };
 const PUSH_REF: &str = "push [rax]";
 const PUSH_VAL: &str = "push rax";
+const BOUNDS_FAIL: &str = ".BoundsFail";
+// 領域外アクセスの報告に使う、添字の対象の名前と行
+type Site<'a> = (Option<&'a String>, usize);
 type GenResult = Result<Vec<String>, Vec<String>>;
 fn concat(l: GenResult, r: GenResult) -> GenResult {
-    if l.is_err() {
-        return l;
-    }
-    if r.is_err() {
-        return r;
-    }
-    let ll = l.unwrap();
-    let rl = r.unwrap();
-    Ok([ll, rl].concat())
+    Ok([l?, r?].concat())
 }
 fn concat_multi(results: &[GenResult]) -> GenResult {
     let mut base = Ok(Vec::new());
@@ -33,11 +33,21 @@ fn concat_multi(results: &[GenResult]) -> GenResult {
             break;
         }
     }
-    return base;
+    base
 }
 struct Generator<'a> {
     p: &'a Program,
     jump_count: usize,
+    fident: String,
+    strs: Vec<Vec<u8>>,
+    floats: Vec<(String, usize)>, // 浮動小数点数の定数の値と大きさ。long double以外はビット列で持つ
+    ret: Type,                    // 生成中の関数の戻り値の型
+    ret_slot: Option<usize>,      // 構造体の戻り値の置き場所のアドレスを退避した位置
+    src: &'a str,
+    opts: &'a Options,
+    bounds_fail: bool, // 領域外アクセスの報告処理を出力する必要があるか
+    uninit: Uninit,
+    aliases: BTreeSet<String>, // オペランドに書けないため別名で参照するシンボル
     _array_size: HashMap<(&'a String, usize), Vec<usize>>,
 }
 const FARG_REGS: [Register; 6] = [
@@ -48,16 +58,489 @@ const FARG_REGS: [Register; 6] = [
     Register::_8,
     Register::_9,
 ];
-fn push_ref(t: &Type) -> Vec<String> {
+const FARG_SSE: usize = 8; // xmm0からxmm7まで
+// 初期化されていないローカル変数の読み出しを調べる。変数はrbpからのオフセットで引く
+#[derive(Default)]
+struct Uninit {
+    vars: HashMap<usize, String>, // 初期化子が無い変数
+    // 初期化子が無い変数のうち、今の位置へ至るどの経路でも代入済みのもの。Noneなら今の位置へは到達しない
+    assigned: Option<HashSet<usize>>,
+    gotos: HashMap<String, HashSet<usize>>, // ラベルへ飛ぶすべてのgotoで代入済みの変数
+    back: HashMap<String, HashSet<usize>>,  // 前回調べたときのgotos。後ろから飛ぶgotoの分に使う
+    reads: Vec<(usize, String)>,            // 代入前の読み出し
+}
+// 合流点では、合流するすべての経路で代入済みの変数だけが代入済みになる。到達しない経路は数えない
+fn meet(a: Option<HashSet<usize>>, b: Option<&HashSet<usize>>) -> Option<HashSet<usize>> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.intersection(b).copied().collect()),
+        (a, None) => a,
+        (None, b) => b.cloned(),
+    }
+}
+// 初期値があれば.data、なければゼロ埋めされる.bssに置く。constなら読み取り専用の領域に置くが、
+// アドレスを初期値に持つものは実行時の再配置が必要なので.data.rel.roに置く
+fn gvar_section(g: &GVar) -> &'static str {
+    match &g.init {
+        _ if !g.type_.qualifiers().is_const => match g.init {
+            Some(_) => ".data",
+            None => ".bss",
+        },
+        Some(init) if has_address(init) => ".section .data.rel.ro",
+        _ => ".section .rodata",
+    }
+}
+fn has_address(init: &GInit) -> bool {
+    match init {
+        GInit::Str(_) | GInit::Addr(..) => true,
+        GInit::List(items) => items.iter().any(|(_, _, i)| has_address(i)),
+        _ => false,
+    }
+}
+// 外部リンケージのシンボルだけを他のオブジェクトに公開する
+fn linkage_directive(linkage: &Linkage, label: &str) -> String {
+    match linkage {
+        Linkage::External => format!(".globl {}", label),
+        Linkage::Internal => format!(".local {}", label),
+    }
+}
+// 引数の置き場所。浮動小数点数はxmm、それ以外は汎用レジスタを前から使い、溢れたらスタックに置く
+// long doubleは常にスタック渡し。Stackは引数領域の先頭からのオフセットを持つ
+enum ArgLoc {
+    Gp(usize),
+    Sse(usize),
+    Stack(usize),
+    Struct(Vec<ArgLoc>), // レジスタで渡す構造体。8byteごとにGpかSseを使う
+}
+// 構造体の8byteごとの分類。浮動小数点数だけを含むならSSE、それ以外はINTEGER
+#[derive(Clone, Copy, PartialEq)]
+enum Class {
+    Integer,
+    Sse,
+}
+// gp個の汎用レジスタは戻り値の置き場所を渡すのに使われている
+fn classify(types: &[Type], mut gp: usize) -> Vec<ArgLoc> {
+    let (mut sse, mut stack) = (0, 0);
+    types
+        .iter()
+        .map(|t| {
+            let classes = match t.base() {
+                Type::LongDouble => None,
+                Type::Struct(_) => classify_struct(t),
+                t if t.is_float() => Some(vec![Class::Sse]),
+                _ => Some(vec![Class::Integer]),
+            };
+            // 構造体は全体がレジスタに収まらなければ、丸ごとスタックに置く
+            if let Some(classes) = classes {
+                let need_gp = classes.iter().filter(|c| **c == Class::Integer).count();
+                if gp + need_gp <= FARG_REGS.len() && sse + classes.len() - need_gp <= FARG_SSE {
+                    let mut regs: Vec<ArgLoc> = classes
+                        .iter()
+                        .map(|c| match c {
+                            Class::Integer => {
+                                gp += 1;
+                                ArgLoc::Gp(gp - 1)
+                            }
+                            Class::Sse => {
+                                sse += 1;
+                                ArgLoc::Sse(sse - 1)
+                            }
+                        })
+                        .collect();
+                    return match t.base() {
+                        Type::Struct(_) => ArgLoc::Struct(regs),
+                        _ => regs.pop().unwrap(),
+                    };
+                }
+            }
+            stack = align_to(stack, t.alignof().max(IDENTITY_OFFSET)) + slot_size(t);
+            ArgLoc::Stack(stack - slot_size(t))
+        })
+        .collect()
+}
+// System V ABIに従って構造体を8byteごとに分類する。16byteを超えるかlong doubleを含むならメモリ経由(None)。
+// ただしlong double一つだけの構造体を返すときはst0を使う(x87_struct)
+fn classify_struct(t: &Type) -> Option<Vec<Class>> {
+    let mut fields = vec![];
+    scalar_fields(t, 0, &mut fields);
+    if t.sizeof() > 16 || fields.iter().any(|(_, f)| *f == Type::LongDouble) {
+        return None;
+    }
+    let classes = (0..t.sizeof().div_ceil(IDENTITY_OFFSET)).map(|i| {
+        let mut fields = fields
+            .iter()
+            .filter(|(offset, _)| offset / IDENTITY_OFFSET == i)
+            .peekable();
+        if fields.peek().is_some() && fields.all(|(_, f)| f.is_float()) {
+            Class::Sse
+        } else {
+            Class::Integer
+        }
+    });
+    Some(classes.collect())
+}
+// 構造体や配列を展開したスカラのメンバとその位置
+fn scalar_fields(t: &Type, offset: usize, fields: &mut Vec<(usize, Type)>) {
+    match t.base() {
+        Type::Struct(s) => {
+            for m in s.body.borrow().iter().flat_map(|b| b.members.iter()) {
+                scalar_fields(&m.type_, offset + m.offset, fields);
+            }
+        }
+        Type::Array(a) => {
+            for i in 0..a.1 {
+                scalar_fields(&a.0, offset + i * a.0.sizeof(), fields);
+            }
+        }
+        Type::Bits(b) => fields.push((offset, b.0.unqualified())),
+        t => fields.push((offset, t.clone())),
+    }
+}
+// long double一つだけの構造体はX87に分類され、引数ではメモリ経由だが戻り値はst0で返る
+fn x87_struct(t: &Type) -> bool {
+    let mut fields = vec![];
+    scalar_fields(t, 0, &mut fields);
+    matches!(t.base(), Type::Struct(_))
+        && t.sizeof() == 16
+        && fields
+            .iter()
+            .all(|(offset, f)| *offset == 0 && *f == Type::LongDouble)
+}
+// 構造体を返すとき、メモリ経由なら呼び出し元が置き場所のアドレスをrdiで渡す
+fn returns_in_memory(t: &Type) -> bool {
+    matches!(t.base(), Type::Struct(_)) && classify_struct(t).is_none() && !x87_struct(t)
+}
+// スタック上で値が占める大きさ。long doubleは2つ分、構造体は8byte単位で中身の分を使う
+fn slot_size(t: &Type) -> usize {
+    match t.base() {
+        Type::LongDouble => 16,
+        Type::Struct(_) => align_to(t.sizeof(), IDENTITY_OFFSET),
+        _ => IDENTITY_OFFSET,
+    }
+}
+// addr+offsetからsizeバイト(8以下)をregへゼロ拡張して読む。構造体の外を読まないよう、端数は分けて読んで組み立てる
+fn load_bytes(reg: &Register, addr: &str, offset: usize, size: usize) -> Vec<String> {
+    let mut lines = vec![];
+    let mut done = 0;
+    for width in [8, 4, 2, 1] {
+        if size - done < width {
+            continue;
+        }
+        let to = if done == 0 { reg } else { &Register::_11 };
+        lines.push(load_zx(
+            width,
+            to,
+            &format!("{}+0x{:X}", addr, offset + done),
+        ));
+        if done > 0 {
+            lines.push(format!("shl r11, {}", done * 8));
+            lines.push(format!("or {}, r11", register(8, reg)));
+        }
+        done += width;
+    }
+    lines
+}
+// regの下位sizeバイト(8以下)をaddr+offsetへ書き込む。regの値は壊れる
+fn store_bytes(reg: &Register, addr: &str, offset: usize, size: usize) -> Vec<String> {
+    let mut lines = vec![];
+    let (mut done, mut prev) = (0, 0);
+    for width in [8, 4, 2, 1] {
+        if size - done < width {
+            continue;
+        }
+        if done > 0 {
+            lines.push(format!("shr {}, {}", register(8, reg), prev * 8));
+        }
+        lines.push(format!(
+            "mov [{}+0x{:X}], {}",
+            addr,
+            offset + done,
+            register(width, reg)
+        ));
+        prev = width;
+        done += width;
+    }
+    lines
+}
+// raxが指す先へスタックの先頭のlong doubleを書き込む。値はスタックに残す
+fn store_ldouble() -> Vec<String> {
+    vec![
+        "mov rdi, [rsp]".into(),
+        "mov [rax], rdi".into(),
+        "mov rdi, [rsp+8]".into(),
+        "mov [rax+8], rdi".into(),
+    ]
+}
+// スタックの先頭のtの値をpopし、raxが指す先に書き込む
+fn store_top(t: &Type) -> Vec<String> {
+    match t {
+        Type::LongDouble => [store_ldouble(), vec!["add rsp, 16".into()]].concat(),
+        // 構造体の値はアドレスなので中身をコピーする
+        Type::Struct(_) => [vec!["pop rdi".into()], copy_memory(t.sizeof())].concat(),
+        Type::Bits(b) => store_bits(&b.0, b.1),
+        _ => vec![
+            "pop rdi".into(),
+            format!(
+                "mov {}[rax], {}",
+                size_directive(t),
+                register(t.sizeof(), &Register::Di)
+            ),
+        ],
+    }
+}
+// ビットフィールドへの書き込み。raxの指す単位全体を読み、フィールドのビットだけを差し替えて書き戻す。
+// rdiには書き込んだ値をフィールドの型で読み直した値が残る
+fn store_bits(t: &Type, b: BitField) -> Vec<String> {
+    let mask = (u64::MAX >> (64 - b.width)) << b.shift;
     vec![
+        "pop rdi".into(),
+        load_zx(t.sizeof(), &Register::Cx, "rax"),
+        format!("mov rdx, 0x{:X}", !mask),
+        "and rcx, rdx".into(),
+        "mov rsi, rdi".into(),
+        format!("shl rsi, {}", 64 - b.width),
+        format!("shr rsi, {}", 64 - b.width - b.shift),
+        "or rcx, rsi".into(),
+        format!(
+            "mov {}[rax], {}",
+            size_directive(t),
+            register(t.sizeof(), &Register::Cx)
+        ),
+        format!("shl rdi, {}", 64 - b.width),
+        format!(
+            "{} rdi, {}",
+            if t.is_signed() { "sar" } else { "shr" },
+            64 - b.width
+        ),
+    ]
+}
+// addrの指す先のsizeバイトをregへゼロ拡張して読む
+fn load_zx(size: usize, reg: &Register, addr: &str) -> String {
+    match size {
+        8 => format!("mov {}, [{}]", register(8, reg), addr),
+        4 => format!("mov {}, DWORD PTR [{}]", register(4, reg), addr),
+        2 => format!("movzx {}, WORD PTR [{}]", register(4, reg), addr),
+        _ => format!("movzx {}, BYTE PTR [{}]", register(4, reg), addr),
+    }
+}
+// 浮動小数点数の値はビット列のまま汎用レジスタで持ち、演算の時だけraxならxmm0、rdiならxmm1へ移す
+fn xmm(reg: &Register) -> &'static str {
+    match reg {
+        Register::Di => "xmm1",
+        _ => "xmm0",
+    }
+}
+fn to_xmm(reg: &Register) -> String {
+    format!("movq {}, {}", xmm(reg), register(8, reg))
+}
+fn from_xmm(t: &Type, reg: &Register) -> String {
+    match t {
+        Type::Float => format!("movd {}, {}", register(4, reg), xmm(reg)),
+        _ => format!("movq {}, {}", register(8, reg), xmm(reg)),
+    }
+}
+// SSEの命令名。floatなら単精度、doubleなら倍精度を使う
+fn sse(t: &Type, op: &str) -> String {
+    format!("{}{}", op, if let Type::Float = t { "ss" } else { "sd" })
+}
+// rax op rdiを浮動小数点数で計算し、結果をraxに置く
+fn float_op(t: &Type, op: &str) -> Vec<String> {
+    vec![
+        to_xmm(&Register::_Ax),
+        to_xmm(&Register::Di),
+        format!("{} xmm0, xmm1", sse(t, op)),
+        from_xmm(t, &Register::_Ax),
+    ]
+}
+// 条件式の値を0と比べる。浮動小数点数は符号ビットを除いたビット列が0なら偽になる
+fn test_cond(t: &Type) -> Vec<String> {
+    let mut lines = vec!["pop rax".to_string()];
+    match t {
+        Type::Float => lines.push("shl eax, 1".into()),
+        Type::Double => lines.push("shl rax, 1".into()),
+        // long doubleは仮数部と、上位の符号を除いた指数部を見る
+        Type::LongDouble => lines.extend(vec![
+            "pop rdi".into(),
+            "and edi, 0x7FFF".into(),
+            "or rax, rdi".into(),
+        ]),
+        _ => {}
+    }
+    lines.push("cmp rax, 0".into());
+    lines
+}
+fn push_ref(t: &Type) -> Vec<String> {
+    let load = if let Type::Array(_) | Type::Func(_) = t {
+        // 配列や関数は値として読むと先頭要素や関数のアドレスになるので読み込まない
+        format!("# {:?} decays to address", t)
+    } else if let Type::Struct(_) = t {
+        // 構造体はレジスタに収まらないので、値の代わりにアドレスで扱う
+        format!("# {:?} is handled by address", t)
+    } else if let Type::Bits(b) = t {
+        // ビットフィールドは単位を読み、上位へ寄せてから戻すことで切り出しと拡張を同時に行う
+        return vec![
+            load_zx(b.0.sizeof(), &Register::_Ax, "rax"),
+            format!("shl rax, {}", 64 - b.1.shift - b.1.width),
+            format!(
+                "{} rax, {}",
+                if b.0.is_signed() { "sar" } else { "shr" },
+                64 - b.1.width
+            ),
+            format!("{} #push_ref", PUSH_VAL),
+        ];
+    } else if let Type::LongDouble = t {
+        // long doubleは上位から2回に分けて積む
+        return vec![
+            "push QWORD PTR [rax+8]".into(),
+            "push QWORD PTR [rax] #push_ref".into(),
+        ];
+    } else if t.is_integer() && t.sizeof() < 8 {
+        // 8byteに満たない整数は、型に合わせてraxへ符号拡張かゼロ拡張して読む
+        let load = match (t.is_signed(), t.sizeof()) {
+            (true, 4) => "movsxd rax",
+            (true, _) => "movsx rax",
+            (false, 4) => "mov eax",
+            (false, _) => "movzx eax",
+        };
+        format!("{}, {}[rax] # {:?}", load, size_directive(t), t)
+    } else {
         format!(
             "mov {}, {}[{}] # {:?}",
             register(t.sizeof(), &Register::_Ax),
-            size_directive(&t),
+            size_directive(t),
             register(8, &Register::_Ax),
             t
-        ),
-        format!("{} #push_ref", PUSH_VAL),
+        )
+    };
+    vec![load, format!("{} #push_ref", PUSH_VAL)]
+}
+// 整数の値は型に合わせて64bitへ拡張した形で持つ。regの値をtで表せる値に切り詰めて拡張し直す
+fn extend(t: &Type, reg: &Register) -> Vec<String> {
+    let r = |size| register(size, reg);
+    match t {
+        Type::Char => vec![format!("movsx {}, {}", r(8), r(1))],
+        Type::UChar => vec![format!("movzx {}, {}", r(4), r(1))],
+        Type::Short => vec![format!("movsx {}, {}", r(8), r(2))],
+        Type::UShort => vec![format!("movzx {}, {}", r(4), r(2))],
+        Type::Int => vec![format!("movsxd {}, {}", r(8), r(4))],
+        Type::UInt => vec![format!("mov {}, {}", r(4), r(4))],
+        _ => vec![],
+    }
+}
+// rdiが指す先からraxが指す先へsizeバイトをコピーする
+fn copy_memory(size: usize) -> Vec<String> {
+    let mut lines = vec![];
+    let mut offset = 0;
+    for width in [8, 4, 1] {
+        while offset + width <= size {
+            let reg = register(width, &Register::_8);
+            lines.push(format!("mov {}, [rdi+0x{:X}]", reg, offset));
+            lines.push(format!("mov [rax+0x{:X}], {}", offset, reg));
+            offset += width;
+        }
+    }
+    lines
+}
+// asmの被演算子に割り当てるレジスタ。rとmは、決まったレジスタや破壊されるレジスタと重ならないものから選ぶ
+const ASM_REGS: [Register; 8] = [
+    Register::Cx,
+    Register::Dx,
+    Register::Si,
+    Register::_8,
+    Register::_9,
+    Register::_11,
+    Register::Di,
+    Register::_Ax,
+];
+fn asm_registers(a: &Asm) -> Result<Vec<Option<Register>>, Vec<String>> {
+    let fixed = |o: &AsmOperand| match o.constraint {
+        'a' => Some(Register::_Ax),
+        'D' => Some(Register::Di),
+        _ => None,
+    };
+    let unknown = |c: &&String| {
+        !matches!(c.as_str(), "memory" | "cc")
+            && register_named(c).is_none()
+            && callee_saved(c).is_none()
+    };
+    if let Some(c) = a.clobbers.iter().find(unknown) {
+        return Err(vec![format!("unknown register name {} in asm", c)]);
+    }
+    // "memory"や"cc"はレジスタではないので何もしない
+    let clobbered: Vec<Register> = a
+        .clobbers
+        .iter()
+        .filter_map(|c| register_named(c))
+        .collect();
+    // =の出力は入力を読み終えた後に書かれるので、入力と同じレジスタでもよい
+    let mut outs = clobbered.clone();
+    let mut ins = [
+        clobbered,
+        a.outputs
+            .iter()
+            .filter(|o| o.read)
+            .filter_map(fixed)
+            .collect(),
+    ]
+    .concat();
+    for (group, taken) in [(&a.outputs, &mut outs), (&a.inputs, &mut ins)] {
+        for reg in group.iter().filter_map(fixed) {
+            if taken.contains(&reg) {
+                return Err(vec![format!(
+                    "{} is used by more than one asm operand or clobber",
+                    register(8, &reg)
+                )]);
+            }
+            taken.push(reg);
+        }
+    }
+    let mut used = [outs, ins].concat();
+    a.outputs
+        .iter()
+        .chain(a.inputs.iter())
+        .map(|o| match (o.constraint, fixed(o)) {
+            ('i', _) => Ok(None),
+            (_, Some(reg)) => Ok(Some(reg)),
+            _ => {
+                let Some(reg) = ASM_REGS.into_iter().find(|r| !used.contains(r)) else {
+                    return Err(vec!["impossible register constraints in asm".into()]);
+                };
+                used.push(reg);
+                Ok(Some(reg))
+            }
+        })
+        .collect()
+}
+fn register_named(name: &str) -> Option<Register> {
+    let name = name.trim_start_matches('%');
+    ASM_REGS
+        .into_iter()
+        .find(|r| [1, 2, 4, 8].iter().any(|size| register(*size, r) == name))
+}
+// 呼び出し先で保存すべきレジスタなら、その64bitの名前
+fn callee_saved(name: &str) -> Option<&'static str> {
+    match name.trim_start_matches('%') {
+        "rbx" | "ebx" | "bx" | "bl" => Some("rbx"),
+        name => ["r12", "r13", "r14", "r15"].into_iter().find(|r| {
+            name.strip_prefix(r)
+                .is_some_and(|s| matches!(s, "" | "d" | "w" | "b"))
+        }),
+    }
+}
+// メモリの被演算子に付ける大きさの指定
+fn ptr_directive(size: usize) -> &'static str {
+    match size {
+        1 => "BYTE PTR ",
+        2 => "WORD PTR ",
+        4 => "DWORD PTR ",
+        _ => "QWORD PTR ",
+    }
+}
+// 関数の後始末をして呼び出し元へ戻る
+fn leave() -> Vec<String> {
+    vec![
+        "mov rsp, rbp".into(),
+        "pop rbp".into(),
+        "ret #<- eplg".into(),
     ]
 }
 impl Generator<'_> {
@@ -66,76 +549,625 @@ impl Generator<'_> {
         self.jump_count += 1;
         label
     }
-    fn fcall(&mut self, f: &Fcall) -> GenResult {
-        let mut lines = Vec::new();
-        for e in f.args.iter().rev() {
+    // ユーザー定義のラベルは関数名で修飾する。識別子に'.'は含まれないので他の関数や.IfEndなどとは衝突しない
+    fn user_label(&self, ident: &str) -> String {
+        format!(".Label.{}.{}", self.fident, ident)
+    }
+    // 文字列リテラルは.rodataに置き、出現順の連番でラベルを振る
+    fn str_label(&mut self, s: &[u8]) -> String {
+        self.strs.push(s.to_vec());
+        format!(".LC{}", self.strs.len() - 1)
+    }
+    // 浮動小数点数の定数も.rodataに置く
+    fn float_label(&mut self, f: f64, t: &Type) -> String {
+        self.floats.push(match t {
+            Type::Float => ((f as f32).to_bits().to_string(), 4),
+            _ => (f.to_bits().to_string(), 8),
+        });
+        format!(".LF{}", self.floats.len() - 1)
+    }
+    fn ldouble_label(&mut self, text: &str) -> String {
+        self.floats.push((text.to_string(), 16));
+        format!(".LF{}", self.floats.len() - 1)
+    }
+    // スタックの先頭のtの値をpopし、x87のst0に積む
+    fn x87_load(&mut self, t: &Type) -> Vec<String> {
+        match t.decay() {
+            Type::LongDouble => vec!["fld TBYTE PTR [rsp]".into(), "add rsp, 16".into()],
+            Type::Double => vec!["fld QWORD PTR [rsp]".into(), "add rsp, 8".into()],
+            Type::Float => vec!["fld DWORD PTR [rsp]".into(), "add rsp, 8".into()],
+            // fildは符号付きとして読むので、最上位ビットが立っていれば2^64を足す
+            Type::ULong | Type::ULongLong => {
+                let label = format!(".U2LD{}", self.jump_label());
+                let two64 = self.float_label(18446744073709551616.0, &Type::Float);
+                vec![
+                    "fild QWORD PTR [rsp]".into(),
+                    "cmp QWORD PTR [rsp], 0".into(),
+                    format!("jge {}", label),
+                    format!("fadd DWORD PTR [rip+{}]", two64),
+                    format!("{}:", label),
+                    "add rsp, 8".into(),
+                ]
+            }
+            _ => vec!["fild QWORD PTR [rsp]".into(), "add rsp, 8".into()],
+        }
+    }
+    // x87のst0をpopし、tの値としてスタックに積む
+    fn x87_store(&mut self, t: &Type) -> Vec<String> {
+        match t {
+            Type::LongDouble => vec!["sub rsp, 16".into(), "fstp TBYTE PTR [rsp]".into()],
+            Type::Double => vec!["push 0".into(), "fstp QWORD PTR [rsp]".into()],
+            Type::Float => vec!["push 0".into(), "fstp DWORD PTR [rsp]".into()],
+            // 2^63以上は符号付きの変換では表せないので、2^63を引いてから変換して最上位ビットを立てる
+            Type::ULong | Type::ULongLong => {
+                let label = self.jump_label();
+                let two63 = self.float_label(9223372036854775808.0, &Type::Float);
+                vec![
+                    "push 0".into(),
+                    format!("fld DWORD PTR [rip+{}]", two63),
+                    "fcomip st, st(1)".into(),
+                    format!("jbe .LD2U{}", label),
+                    "fisttp QWORD PTR [rsp]".into(),
+                    format!("jmp .LD2UEnd{}", label),
+                    format!(".LD2U{}:", label),
+                    format!("fsub DWORD PTR [rip+{}]", two63),
+                    "fisttp QWORD PTR [rsp]".into(),
+                    "btc QWORD PTR [rsp], 63".into(),
+                    format!(".LD2UEnd{}:", label),
+                ]
+            }
+            // 小数部は切り捨てる
+            _ => [
+                vec![
+                    "push 0".into(),
+                    "fisttp QWORD PTR [rsp]".into(),
+                    "pop rax".into(),
+                ],
+                extend(t, &Register::_Ax),
+                vec![PUSH_VAL.into()],
+            ]
+            .concat(),
+        }
+    }
+    // long doubleの二項演算の両辺をpopし、st1に左辺、st0に右辺を置く
+    fn x87_operands(&mut self, l: &Type, r: &Type) -> Vec<String> {
+        [
+            self.x87_load(r),
+            self.x87_load(l),
+            vec!["fxch st(1)".into()],
+        ]
+        .concat()
+    }
+    // regの値をfromの型からtoの型へ変換する。整数同士なら切り詰めと拡張だけで済む
+    fn convert(&mut self, from: &Type, to: &Type, reg: &Register) -> Vec<String> {
+        let (from, to) = (from.decay(), to.decay());
+        let (r, x) = (register(8, reg), xmm(reg));
+        match (from.is_float(), to.is_float()) {
+            (false, false) => extend(&to, reg),
+            (true, true) if from == to => vec![],
+            (true, true) => {
+                let cvt = if let Type::Float = to {
+                    "cvtsd2ss"
+                } else {
+                    "cvtss2sd"
+                };
+                vec![
+                    to_xmm(reg),
+                    format!("{} {}, {}", cvt, x, x),
+                    from_xmm(&to, reg),
+                ]
+            }
+            // 2^63以上は符号付きの変換では表せないので、2^63を引いてから変換して最上位ビットを立てる
+            (true, false) if matches!(to, Type::ULong | Type::ULongLong) => {
+                let label = self.jump_label();
+                let limit = if let Type::Float = from {
+                    0x5F000000u64
+                } else {
+                    0x43E0000000000000
+                };
+                let cvt = if let Type::Float = from {
+                    "cvttss2si"
+                } else {
+                    "cvttsd2si"
+                };
+                vec![
+                    to_xmm(reg),
+                    format!("mov r11, 0x{:X}", limit),
+                    "movq xmm2, r11".into(),
+                    format!("{} {}, xmm2", sse(&from, "comi"), x),
+                    format!("jae .F2U{}", label),
+                    format!("{} {}, {}", cvt, r, x),
+                    format!("jmp .F2UEnd{}", label),
+                    format!(".F2U{}:", label),
+                    format!("{} {}, xmm2", sse(&from, "sub"), x),
+                    format!("{} {}, {}", cvt, r, x),
+                    format!("btc {}, 63", r),
+                    format!(".F2UEnd{}:", label),
+                ]
+            }
+            // 小数部は切り捨てる
+            (true, false) => {
+                let cvt = if let Type::Float = from {
+                    "cvttss2si"
+                } else {
+                    "cvttsd2si"
+                };
+                [
+                    vec![to_xmm(reg), format!("{} {}, {}", cvt, r, x)],
+                    extend(&to, reg),
+                ]
+                .concat()
+            }
+            // 最上位ビットが立った符号なし整数は、半分にしてから変換して2倍する。切り捨てたビットは丸めのために残す
+            (false, true) if matches!(from, Type::ULong | Type::ULongLong) => {
+                let label = self.jump_label();
+                let cvt = sse(&to, "cvtsi2");
+                vec![
+                    format!("test {}, {}", r, r),
+                    format!("js .U2F{}", label),
+                    format!("{} {}, {}", cvt, x, r),
+                    format!("jmp .U2FEnd{}", label),
+                    format!(".U2F{}:", label),
+                    format!("mov r11, {}", r),
+                    "shr r11, 1".into(),
+                    format!("and {}, 1", r),
+                    format!("or r11, {}", r),
+                    format!("{} {}, r11", cvt, x),
+                    format!("{} {}, {}", sse(&to, "add"), x, x),
+                    format!(".U2FEnd{}:", label),
+                    from_xmm(&to, reg),
+                ]
+            }
+            (false, true) => vec![
+                format!("{} {}, {}", sse(&to, "cvtsi2"), x, r),
+                from_xmm(&to, reg),
+            ],
+        }
+    }
+    // 二項演算の両辺をpopし、揃えた型へ変換する
+    fn pop_operands(&mut self, l: &Type, r: &Type, common: &Type) -> Vec<String> {
+        [
+            vec!["pop rdi".into(), "pop rax".into()],
+            self.convert(l, common, &Register::_Ax),
+            self.convert(r, common, &Register::Di),
+        ]
+        .concat()
+    }
+    // スタックの先頭の値をfromの型からtoの型へ変換する
+    fn convert_top(&mut self, from: &Type, to: &Type) -> Vec<String> {
+        // long doubleが関わる変換はx87を経由する
+        if from.decay() != to.decay() && (*from == Type::LongDouble || *to == Type::LongDouble) {
+            return [self.x87_load(from), self.x87_store(to)].concat();
+        }
+        let lines = self.convert(from, to, &Register::_Ax);
+        if lines.is_empty() {
+            return lines;
+        }
+        [vec!["pop rax".into()], lines, vec![PUSH_VAL.into()]].concat()
+    }
+    fn fcall(&mut self, f: &Fcall, ret: &Type) -> GenResult {
+        let call = format!("call {}", self.sym(&f.ident));
+        self.call(&f.args, &f.params, vec![], call, ret, f.ret_slot)
+    }
+    // 関数ポインタの呼び出し。呼び出し先は引数を積んだ後に求め、引数用でないr10に移して呼ぶ
+    fn call_indirect(&mut self, c: &UnaryCall, ret: &Type) -> GenResult {
+        let mut callee = self.unary(&c.callee, true)?;
+        callee.push("pop r10".into());
+        let lines = self.call(
+            &c.args,
+            &c.params,
+            callee,
+            "call r10".into(),
+            ret,
+            c.ret_slot,
+        )?;
+        Ok(self.negate(lines, &c.sign, ret))
+    }
+    // 戻り値の上位ビットは不定なので、戻り値の型に合わせて拡張し直す。
+    // 構造体の戻り値はret_slotの領域に置き、そのアドレスを値とする
+    fn call(
+        &mut self,
+        args: &[Typed<Expr>],
+        params: &[Type],
+        callee: Vec<String>,
+        call: String,
+        ret: &Type,
+        ret_slot: Option<usize>,
+    ) -> GenResult {
+        let hidden = returns_in_memory(ret);
+        let locs = classify(params, hidden as usize);
+        let (stacked, in_regs): (Vec<usize>, Vec<usize>) =
+            (0..args.len()).partition(|i| matches!(locs[*i], ArgLoc::Stack(_)));
+        // call時点でrspを16byte境界に揃える必要がある。積まれている値の数は静的にわからないので、
+        // 元のrspを退避してから切り揃える
+        let mut lines = vec![
+            "mov rax, rsp # align ->".into(),
+            "and rsp, -16".into(),
+            "sub rsp, 8".into(),
+            "push rax # <- align".into(),
+        ];
+        // スタック渡しの引数を逆順で積む。引数領域での位置に合うよう、間に隙間を空ける
+        let offset = |i: usize| match locs[i] {
+            ArgLoc::Stack(offset) => offset,
+            _ => 0,
+        };
+        let stack_size = stacked
+            .last()
+            .map_or(0, |&i| align_to(offset(i) + slot_size(&params[i]), 16));
+        let mut top = stack_size;
+        for &i in stacked.iter().rev() {
+            let end = offset(i) + slot_size(&params[i]);
+            if top > end {
+                lines.push(format!("sub rsp, {}", top - end));
+            }
+            let e = &args[i];
             lines.extend(self.expr(&(&e.0, e.1.clone()))?);
+            lines.extend(self.convert_top(&e.1, &params[i]));
+            if let Type::Struct(_) = params[i].base() {
+                // 構造体はアドレスの代わりに中身をスタックに置く
+                lines.extend(vec![
+                    "pop rdi".into(),
+                    format!("sub rsp, {}", slot_size(&params[i])),
+                    "mov rax, rsp".into(),
+                ]);
+                lines.extend(copy_memory(params[i].sizeof()));
+            }
+            top = offset(i);
         }
-
-        // 6つまではレジスタ経由。rdi,rsi,rdx,rx,r8,r9の順。
-        // 7つ目以降の引数はrbpの前に逆順で積む
-        for i in 0..(cmp::min(f.args.len(), FARG_REGS.len())) {
-            let r = FARG_REGS.iter().nth(i).unwrap();
-            lines.extend(vec![format!("pop {}", register(0, r))]);
+        // その上にレジスタ渡しの引数を逆順で積む
+        for &i in in_regs.iter().rev() {
+            let e = &args[i];
+            lines.extend(self.expr(&(&e.0, e.1.clone()))?);
+            lines.extend(self.convert_top(&e.1, &params[i]));
         }
-        lines.push(format!("call {}", f.ident,));
+        lines.extend(callee);
 
+        // 整数はrdi,rsi,rdx,rcx,r8,r9、浮動小数点数はxmm0からxmm7の順
+        let mut sse_count = 0;
+        for &i in in_regs.iter() {
+            match &locs[i] {
+                ArgLoc::Gp(n) => lines.push(format!("pop {}", register(8, &FARG_REGS[*n]))),
+                ArgLoc::Sse(n) => {
+                    lines.extend(vec!["pop rax".into(), format!("movq xmm{}, rax", n)]);
+                    sse_count += 1;
+                }
+                // 構造体は8byteずつ読んでレジスタに載せる
+                ArgLoc::Struct(regs) => {
+                    lines.push("pop rax".into());
+                    let size = params[i].sizeof();
+                    for (j, loc) in regs.iter().enumerate() {
+                        let offset = j * IDENTITY_OFFSET;
+                        let width = (size - offset).min(IDENTITY_OFFSET);
+                        match loc {
+                            ArgLoc::Gp(n) => {
+                                lines.extend(load_bytes(&FARG_REGS[*n], "rax", offset, width))
+                            }
+                            ArgLoc::Sse(n) => {
+                                lines.extend(load_bytes(&Register::_11, "rax", offset, width));
+                                lines.push(format!("movq xmm{}, r11", n));
+                                sse_count += 1;
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                ArgLoc::Stack(_) => {}
+            }
+        }
+        // メモリ経由で返る構造体は、置き場所のアドレスを最初の引数として渡す
+        if hidden {
+            lines.extend(vec![
+                "mov rdi, rbp".into(),
+                format!("sub rdi, {}", ret_slot.unwrap()),
+            ]);
+        }
+        // 可変長引数の関数は、alで渡されたxmmの数を見る
+        lines.push(format!("mov eax, {}", sse_count));
+        lines.push(call);
+        lines.push(format!("add rsp, {}", stack_size));
+        lines.push("pop rsp".into());
+        if let Type::Struct(_) = ret.base() {
+            lines.extend(self.receive_struct(ret, ret_slot.unwrap()));
+            return Ok(lines);
+        }
+        // long doubleの戻り値はst0で返る
+        if let Type::LongDouble = ret {
+            lines.extend(self.x87_store(ret));
+            return Ok(lines);
+        }
+        if ret.is_float() {
+            lines.push(from_xmm(ret, &Register::_Ax));
+        } else {
+            lines.extend(extend(ret, &Register::_Ax));
+        }
         lines.push(PUSH_VAL.into());
         Ok(lines)
     }
-    fn primary(&mut self, m: &Typed<Primary>, arr: &Vec<Typed<Expr>>, is_rvar: bool) -> GenResult {
+    // レジスタで返った構造体をslotの領域へ書き出し、そのアドレスを積む。メモリ経由ならraxがそのアドレス
+    fn receive_struct(&self, ret: &Type, slot: usize) -> Vec<String> {
+        if x87_struct(ret) {
+            return vec![
+                "mov rsi, rbp".into(),
+                format!("sub rsi, {}", slot),
+                "fstp TBYTE PTR [rsi]".into(),
+                "push rsi".into(),
+            ];
+        }
+        let Some(classes) = classify_struct(ret) else {
+            return vec![PUSH_VAL.into()];
+        };
+        let mut lines = vec!["mov rsi, rbp".into(), format!("sub rsi, {}", slot)];
+        let (mut gp, mut sse) = (0, 0);
+        for (i, class) in classes.iter().enumerate() {
+            let offset = i * IDENTITY_OFFSET;
+            let width = (ret.sizeof() - offset).min(IDENTITY_OFFSET);
+            let reg = match class {
+                Class::Integer => {
+                    gp += 1;
+                    if gp == 1 { Register::_Ax } else { Register::Dx }
+                }
+                Class::Sse => {
+                    sse += 1;
+                    lines.push(format!("movq r11, xmm{}", sse - 1));
+                    Register::_11
+                }
+            };
+            lines.extend(store_bytes(&reg, "rsi", offset, width));
+        }
+        lines.push("push rsi".into());
+        lines
+    }
+    fn primary(&mut self, m: &Typed<Primary>, is_rvar: bool) -> GenResult {
         if !is_rvar && !m.0.is_lvar() {
             return Err(vec![LEFT_VALUE_IS_NOT_ASSIGNABLE.into()]);
         }
-        let mut lines = match &m.0.node.0 {
-            PrimaryNode::Num(n) => Ok(vec![format!("push {}", n.0)]),
-            PrimaryNode::Expr(e) => self.expr(&(e, m.1.clone())), // TODO これだとこれに直接配列アクセスしようとしたら困りそう。これの戻り値がLvであるとわからないと難しい。右式の変数がアドレスからその内部の値に姿を変えるのは代入演算子('=')によるものだと解釈するほうが良いのでは？　ひとまずExprに対する配列アクセスはサポートしない
-            PrimaryNode::Fcall(f) => self.fcall(f),
-            PrimaryNode::Lv(Lvar::Id(i)) => {
-                let mut lines = vec!["mov rax, rbp".into(), format!("sub rax, {}", i.offset)];
-                if is_rvar && arr.len() == 0 {
-                    lines.extend(push_ref(&i._type_));
+        let lines = match &m.0.node.0 {
+            // pushの即値は32bitまでなので、それを超える値は一度raxを経由する
+            PrimaryNode::Num((n, _)) if i32::try_from(*n).is_ok() => {
+                Ok(vec![format!("push {}", n)])
+            }
+            PrimaryNode::Num((n, _)) => Ok(vec![format!("mov rax, {}", n), PUSH_VAL.into()]),
+            PrimaryNode::Float(f) => {
+                let t = &m.0.node.1;
+                let label = self.float_label(*f, t);
+                Ok(vec![
+                    format!(
+                        "mov {}, {}[rip+{}]",
+                        register(t.sizeof(), &Register::_Ax),
+                        size_directive(t),
+                        label
+                    ),
+                    PUSH_VAL.into(),
+                ])
+            }
+            PrimaryNode::LDouble(s) => {
+                let label = self.ldouble_label(s);
+                Ok(vec![
+                    format!("push QWORD PTR [rip+{}+8]", label),
+                    format!("push QWORD PTR [rip+{}]", label),
+                ])
+            }
+            PrimaryNode::Str(s) => Ok(vec![
+                format!("lea rax, [rip+{}]", self.str_label(s)),
+                PUSH_VAL.into(),
+            ]),
+            PrimaryNode::Expr(e) => self.expr(&(e, m.1.clone())),
+            PrimaryNode::Fcall(f) => self.fcall(f, &m.1),
+            PrimaryNode::Lv(l) => {
+                if let Lvar::Id(i) = l
+                    && is_rvar
+                {
+                    self.warn_uninit(i.offset);
+                }
+                let mut lines = match l {
+                    Lvar::Id(i) => vec!["mov rax, rbp".into(), format!("sub rax, {}", i.offset)],
+                    Lvar::Global(i) => vec![format!("lea rax, [rip+{}]", self.sym(&i.name))],
+                };
+                if is_rvar {
+                    lines.extend(push_ref(&m.0.node.1));
+                } else {
+                    lines.push(PUSH_VAL.into());
+                };
+                Ok(lines)
+            }
+            // 評価するたびに初期化し直し、その領域を変数と同じく左辺値として扱う
+            PrimaryNode::Compound(c) => {
+                let mut lines = self.init_list(&c.def, &c.elems)?;
+                lines.extend(vec![
+                    "mov rax, rbp".into(),
+                    format!("sub rax, {}", c.def.offset),
+                ]);
+                if is_rvar {
+                    lines.extend(push_ref(&m.0.node.1));
                 } else {
                     lines.push(PUSH_VAL.into());
                 };
                 Ok(lines)
             }
         }?;
-        if arr.len() == 0 {
-            return Ok(lines);
+        Ok(lines)
+    }
+    // p[i]は*(p+i)と同じ。添字の対象は値として評価すれば、配列なら先頭要素のアドレス、ポインタならその値になる
+    fn subscript(&mut self, v: &UnaryVar, is_rvar: bool) -> GenResult {
+        let m = &v.prim;
+        match &m.1 {
+            // 可変長配列は各次元の大きさをスロットの上から読むので、スロットのアドレスを起点にする
+            Type::Vla(b) => {
+                let lines = self.primary(m, false)?;
+                let indexes = self.indexes(&v._arrs)?;
+                self.vla_index(lines, b, indexes, (m.0.ident(), v.line), is_rvar)
+            }
+            Type::Array(_) | Type::Ptr(_) => {
+                let lines = self.primary(m, true)?;
+                let indexes = self.indexes(&v._arrs)?;
+                let site = (m.0.ident(), v.line);
+                self.array_index(lines, &m.1, indexes, site, is_rvar)
+            }
+            t if t.is_integer() => self.commuted_subscript(v, is_rvar),
+            _ => Err(vec!["this node is not array".into()]),
+        }
+    }
+    // 添字がindexにある前提で、boundの範囲外なら報告して終了する。負の添字も符号なしで比べれば範囲外になる
+    fn bounds_check(&mut self, site: Site, dim: usize, index: &str, bound: &str) -> Vec<String> {
+        if !self.opts.bounds_check {
+            return vec![];
+        }
+        self.bounds_fail = true;
+        let (name, line) = site;
+        let name = name.map_or("(expression)", |n| n.as_str());
+        let src = self.src.split('\n').nth(line).unwrap_or("").trim();
+        // 行番号や配列名は埋め込んだ書式文字列をそのまま渡す
+        let msg = format!(
+            "line {}: index %ld is out of bounds for {} (dimension {}, size %ld)\n    {}\n",
+            line + 1,
+            name,
+            dim + 1,
+            src.replace('%', "%%")
+        );
+        let ok_label = format!(".BoundsOk{}", self.jump_label());
+        vec![
+            format!("mov rcx, {}", bound),
+            format!("cmp {}, rcx", index),
+            format!("jb {}", ok_label),
+            format!("lea rsi, [rip+{}]", self.str_label(msg.as_bytes())),
+            format!("mov rdx, {}", index),
+            format!("jmp {}", BOUNDS_FAIL),
+            ok_label + ":",
+        ]
+    }
+    // 領域外アクセスの報告。dprintf(2, rsi, rdx, rcx)してからabortする
+    fn bounds_fail(&self) -> Vec<String> {
+        if !self.bounds_fail {
+            return vec![];
         }
-        // TODO 配列アクセスしてよいやつかどうかチェック
-        let (t, depth) = match &m.1 {
-            Type::Array(b) => {
-                let t = &b.0;
-                let depth = b.1;
-                if depth < arr.len() {
-                    return Err(vec![format!(
-                        "this array has {} dimension, cannot access {} dimension",
-                        depth,
-                        arr.len()
-                    )]);
-                }
-                (t, depth)
-            }
-            _ => return Err(vec!["this node is not array".into()]),
+        vec![
+            format!("{}:", BOUNDS_FAIL),
+            "and rsp, -16".into(),
+            "push rsi".into(),
+            "push rdx".into(),
+            "push rcx".into(),
+            "sub rsp, 8".into(),
+            // それまでの出力を失わないよう先に書き出しておく
+            "mov edi, 0".into(),
+            "call fflush".into(),
+            "add rsp, 8".into(),
+            "pop rcx".into(),
+            "pop rdx".into(),
+            "pop rsi".into(),
+            "mov edi, 2".into(),
+            "mov eax, 0".into(),
+            "call dprintf".into(),
+            "call abort".into(),
+        ]
+    }
+    // 3[a]はa[3]と同じ。添字の対象を起点にして、整数の側を最初の添字として扱う
+    fn commuted_subscript(&mut self, v: &UnaryVar, is_rvar: bool) -> GenResult {
+        let (base, rest) = v._arrs.split_first().unwrap();
+        let prim = match &base.0 {
+            Expr::Asgn(ea) => ea.assign.primary(),
+            Expr::VarAsgn(_) => None,
         };
-        let item_size = t.sizeof_item();
+        let mut indexes = vec![self.primary(&v.prim, true)?];
+        indexes.extend(self.indexes(rest)?);
+        let site = (prim.and_then(|p| p.ident()), v.line);
+        match base.1.unqualified() {
+            // 可変長配列は変数のスロットから各次元の大きさを読むので、変数そのものにしか添字を付けられない
+            Type::Vla(b) => {
+                let Some(p) = prim else {
+                    return Err(vec!["this node is not array".into()]);
+                };
+                let lines = self.primary(&(p.clone(), base.1.clone()), false)?;
+                self.vla_index(lines, &b, indexes, site, is_rvar)
+            }
+            t => {
+                let lines = self.expr(&(&base.0, base.1.clone()))?;
+                self.array_index(lines, &t, indexes, site, is_rvar)
+            }
+        }
+    }
+    // 添字の値を積むコード
+    fn indexes(&mut self, arrs: &[Typed<Expr>]) -> Result<Vec<Vec<String>>, Vec<String>> {
+        arrs.iter()
+            .map(|a| self.expr(&(&a.0, a.1.clone())))
+            .collect()
+    }
+    // 固定長配列やポインタの指す先の大きさはコンパイル時にわかるので、要素のアドレスは静的なストライドから求める
+    fn array_index(
+        &mut self,
+        mut lines: Vec<String>,
+        t: &Type,
+        indexes: Vec<Vec<String>>,
+        site: Site,
+        is_rvar: bool,
+    ) -> GenResult {
+        let mut t = t.clone();
+        for (ind, index) in indexes.into_iter().enumerate() {
+            if ind > 0 && t.is_pointer() {
+                // ポインタの配列などは、要素として得たポインタの値を次の添字の起点にする
+                lines.extend(vec![
+                    "pop rax".into(),
+                    "mov rax, [rax]".into(),
+                    PUSH_VAL.into(),
+                ]);
+            }
+            let item = t.deref().unwrap();
+            lines.extend(index);
+            lines.push("pop rdi".into());
+            // ポインタの指す先の要素数はわからないので、検査できるのは配列の次元だけ
+            if let Type::Array(arr) = &t {
+                let bound = arr.1.to_string();
+                lines.extend(self.bounds_check(site, ind, "rdi", &bound));
+            }
+            lines.extend(vec![
+                "pop rax".into(),
+                format!("imul rdi, 0x{:X}", item.sizeof()),
+                "add rax, rdi".into(),
+                PUSH_VAL.into(),
+            ]);
+            t = item;
+        }
+        if is_rvar {
+            lines.pop();
+            lines.extend(push_ref(&t));
+        }
+        Ok(lines)
+    }
+    // 可変長配列は変数のスロットに実体へのポインタを持ち、その上に各次元の大きさを持つ
+    fn vla_index(
+        &mut self,
+        mut lines: Vec<String>,
+        b: &(Type, usize),
+        indexes: Vec<Vec<String>>,
+        site: Site,
+        is_rvar: bool,
+    ) -> GenResult {
+        let count = indexes.len();
+        let (t, depth) = b;
+        let depth = *depth;
+        if depth < count {
+            return Err(vec![format!(
+                "this array has {} dimension, cannot access {} dimension",
+                depth, count
+            )]);
+        }
         lines.push("push 0 # arr start".into()); // 配列のオフセット
-        for (ind, a) in arr.iter().enumerate() {
-            lines.extend(self.expr(&(&a.0, a.1.clone()))?);
-            lines.push(format!("#{:?}", a.0));
-            // TODO: 配列の領域外アクセスチェック
+        for (ind, index) in indexes.into_iter().enumerate() {
+            lines.extend(index);
             lines.extend(vec![
                 format!("pop rax # {}", ind), // 算出した要素数
                 "pop rdi".into(),             // 配列のオフセット
-                "pop rsi".into(),             // 配列のアドレス
-                format!("mov rdx, rsi"),
+                "pop rsi".into(),             // 配列のスロットのアドレス
+            ]);
+            if self.opts.bounds_check {
+                lines.extend(self.vla_bound(ind, depth));
+                lines.extend(self.bounds_check(site, ind, "rax", "rcx"));
+            }
+            lines.extend(vec![
                 if ind == depth - 1 {
-                    format!("mov rdx, 1",) // 多次元配列の端っこなら固定値
+                    "mov rdx, 1".to_string() // 多次元配列の端っこなら固定値
                 } else {
                     format!(
-                        "add rdx, 0x{:X}\nmov rdx, [rdx]",
+                        "mov rdx, [rsi+0x{:X}]",
                         (ind + 2) * IDENTITY_OFFSET //(ind + 2)で正しい。配列の実体のポインタの直上は配列全体の大きさを格納している。その一つ上が、1次元目の配列1つあたりのメモリの大きさを表している
                     )
                 },
@@ -148,24 +1180,84 @@ impl Generator<'_> {
         lines.extend(vec![
             "pop rdi # arr end".into(),
             "pop rax".into(),
-            format!("imul rdi, 0x{:X}", item_size),
-            "sub rax, rdi".into(),
-            if is_rvar {
-                PUSH_REF.into()
-            } else {
-                PUSH_VAL.into()
-            },
+            "mov rax, [rax]".into(), // 配列の実体へのポインタ
+            format!("imul rdi, 0x{:X}", t.sizeof()),
+            "add rax, rdi".into(),
         ]);
-
+        if is_rvar && depth == count {
+            lines.extend(push_ref(t));
+        } else {
+            // 一部の次元だけ添字を指定した場合は部分配列のアドレスになる
+            lines.push(PUSH_VAL.into());
+        }
         Ok(lines)
     }
+    // 可変長配列のind次元目の大きさをrcxに求める。スロットの上には深い次元からの積が並んでいるので、隣同士の商になる
+    fn vla_bound(&self, ind: usize, depth: usize) -> Vec<String> {
+        if ind == depth - 1 {
+            return vec![format!("mov rcx, [rsi+0x{:X}]", depth * IDENTITY_OFFSET)];
+        }
+        vec![
+            "mov r8, rax".into(),
+            format!("mov rax, [rsi+0x{:X}]", (ind + 1) * IDENTITY_OFFSET),
+            format!("mov rcx, [rsi+0x{:X}]", (ind + 2) * IDENTITY_OFFSET),
+            // 深い次元に大きさ0のものがあれば全体も0になるので、0除算を避ければ大きさは0と求まる
+            "mov r9, 1".into(),
+            "test rcx, rcx".into(),
+            "cmove rcx, r9".into(),
+            "cqo".into(),
+            "idiv rcx".into(),
+            "mov rcx, rax".into(),
+            "mov rax, r8".into(),
+        ]
+    }
+    fn negate(&self, lines: Vec<String>, sign: &Option<AddSub>, t: &Type) -> Vec<String> {
+        match sign {
+            None | Some(AddSub::Plus) => lines,
+            // 浮動小数点数は符号ビットを反転する。long doubleの符号は10byte目の最上位ビット
+            _ if *t == Type::LongDouble => {
+                [lines, vec!["xor BYTE PTR [rsp+9], 0x80".into()]].concat()
+            }
+            _ if t.is_float() => {
+                let flip = if let Type::Float = t {
+                    "btc eax, 31"
+                } else {
+                    "btc rax, 63"
+                };
+                [lines, vec!["pop rax".into(), flip.into(), PUSH_VAL.into()]].concat()
+            }
+            _ => [
+                lines,
+                vec!["pop rax".into(), "neg rax".into()],
+                extend(&t.promote(), &Register::_Ax),
+                vec![PUSH_VAL.into()],
+            ]
+            .concat(),
+        }
+    }
+    fn cast(&mut self, c: &UnaryCast) -> GenResult {
+        let mut lines = self.unary(&c.unary, true)?;
+        if c.unary.1 == Type::LongDouble || c.to == Type::LongDouble {
+            lines.extend(self.convert_top(&c.unary.1, &c.to));
+        } else {
+            lines.push("pop rax".into());
+            lines.extend(self.convert(&c.unary.1, &c.to, &Register::_Ax));
+            lines.push(PUSH_VAL.into());
+        }
+        Ok(self.negate(lines, &c.sign, &c.to))
+    }
     fn unary(&mut self, u: &Typed<Unary>, is_rvar: bool) -> GenResult {
         if !is_rvar && !u.0.is_lvar() {
             return Err(vec![LEFT_VALUE_IS_NOT_ASSIGNABLE.into()]);
         }
         match &u.0 {
             Unary::Ptr(p) => {
-                let mut pri = self.unary(&p.unary, is_rvar)?;
+                // アドレスを取った変数はポインタ越しに書き込まれうるので、初期化済みとみなす
+                if let PtrOpe::Deref = p.ope {
+                    self.mark_init(p.unary.0.primary());
+                }
+                // *の対象は左辺値として使う場合でも値(=指す先のアドレス)を求める
+                let mut pri = self.unary(&p.unary, is_rvar || matches!(p.ope, PtrOpe::Ref))?;
                 let last = pri.last();
                 if last.is_none() {
                     return Err(vec![
@@ -173,9 +1265,15 @@ impl Generator<'_> {
                     ]);
                 }
                 match p.ope {
-                    PtrOpe::Ref => Ok([pri, vec!["pop rax".into(), PUSH_REF.into()]].concat()),
+                    // 右辺値として読むときは参照先の型の幅で読む。左辺値ならポインタの値がそのまま書き込み先になる
+                    PtrOpe::Ref if is_rvar => match u.1 {
+                        // 可変長配列の部分配列はスロットを持たないので、アドレスがそのまま値になる
+                        Type::Vla(_) => Ok([pri, vec!["pop rax".into(), PUSH_VAL.into()]].concat()),
+                        _ => Ok([pri, vec!["pop rax".into()], push_ref(&u.1)].concat()),
+                    },
+                    PtrOpe::Ref => Ok(pri),
                     PtrOpe::Deref => {
-                        return if last.unwrap().contains(PUSH_REF) {
+                        if last.unwrap().contains(PUSH_REF) {
                             let len = pri.len() - 1;
                             pri[len] = PUSH_VAL.into();
                             Ok(pri)
@@ -186,92 +1284,210 @@ impl Generator<'_> {
                             Ok(pri)
                         } else {
                             Err(vec!["cannot handle multiple dereference".into()])
-                        };
+                        }
                     }
                 }
             }
-            Unary::Var(v) => {
-                let pri = self.primary(&v.prim, &v._arrs, is_rvar)?;
-                match v.prim.0.ope {
-                    None | Some(AddSub::Plus) => {
-                        return Ok(pri);
+            Unary::Sizeof(s) => self.vla_sizeof(&s.unary),
+            Unary::Call(c) => self.call_indirect(c, &u.1),
+            Unary::Cast(c) => self.cast(c),
+            Unary::Member(m) => {
+                // 構造体の値はアドレスなので、.でも->でも左側は値として求めればよい
+                let mut lines = self.unary(&m.unary, true)?;
+                lines.extend(vec![
+                    "pop rax".into(),
+                    format!(
+                        "add rax, 0x{:X} # {}{}",
+                        m.member.offset,
+                        if m.arrow { "->" } else { "." },
+                        m.member.name
+                    ),
+                    PUSH_VAL.into(),
+                ]);
+                if !m._arrs.is_empty() {
+                    // ポインタのメンバは、メンバの値(指す先)を添字の起点にする
+                    if let Type::Ptr(_) = m.member.type_.base() {
+                        lines.pop();
+                        lines.extend(vec!["mov rax, [rax]".into(), PUSH_VAL.into()]);
                     }
-                    _ => {
-                        return Ok([
-                            pri,
-                            vec!["push 0", "pop rdi", "pop rax", "sub rdi, rax", "push rdi"]
-                                .iter()
-                                .map(|s| s.to_string())
-                                .collect(),
-                        ]
-                        .concat());
+                    let site = (Some(&m.member.name), m.line);
+                    let indexes = self.indexes(&m._arrs)?;
+                    lines = self.array_index(lines, &m.member.type_, indexes, site, is_rvar)?;
+                } else if is_rvar {
+                    lines.pop();
+                    // ビットフィールドは式の型でなくメンバの位置に従って読む
+                    let t = if let Type::Bits(_) = m.member.type_ {
+                        &m.member.type_
+                    } else {
+                        &u.1
+                    };
+                    lines.extend(push_ref(t));
+                }
+                Ok(self.negate(lines, &m.sign, &u.1))
+            }
+            Unary::Var(v) => {
+                let pri = if v._arrs.is_empty() {
+                    self.primary(&v.prim, is_rvar)?
+                } else {
+                    self.subscript(v, is_rvar)?
+                };
+                Ok(self.negate(pri, &v.prim.0.ope, &u.1))
+            }
+        }
+    }
+    // 可変長配列のスロットのアドレスと、そこまでに剥がした次元の数を求める
+    fn vla_slot(&mut self, u: &Unary) -> Result<(Vec<String>, usize), Vec<String>> {
+        match u {
+            // sizeof(v)や(v)[1]のような括弧は中身を見る。括弧の後ろの添字の分だけ深くなる
+            Unary::Var(v) if v.prim.0.ope.is_none() => match &v.prim.0.node.0 {
+                PrimaryNode::Expr(e) => match e.as_ref() {
+                    Expr::Asgn(a) if a.assign.unary().is_some() => {
+                        let (lines, depth) = self.vla_slot(a.assign.unary().unwrap())?;
+                        Ok((lines, depth + v._arrs.len()))
                     }
+                    _ => Err(vec!["cannot get size of this variable length array".into()]),
+                },
+                _ if matches!(v.prim.1, Type::Vla(_)) => {
+                    Ok((self.primary(&v.prim, false)?, v._arrs.len()))
                 }
+                _ => Err(vec!["cannot get size of this variable length array".into()]),
+            },
+            Unary::Var(v) if matches!(v.prim.1, Type::Vla(_)) => {
+                Ok((self.primary(&v.prim, false)?, v._arrs.len()))
+            }
+            Unary::Ptr(p) if matches!(p.ope, PtrOpe::Ref) => {
+                let (lines, depth) = self.vla_slot(&p.unary.0)?;
+                Ok((lines, depth + 1))
             }
+            _ => Err(vec!["cannot get size of this variable length array".into()]),
         }
     }
+    // スロットの上にはdepth次元目以降の要素数の積が置かれているので、末端の要素の大きさを掛ければよい
+    fn vla_sizeof(&mut self, u: &Typed<Unary>) -> GenResult {
+        let item = match &u.1 {
+            Type::Vla(b) => b.0.sizeof(),
+            t => return Err(vec![format!("{:?} is not a variable length array", t)]),
+        };
+        let (mut lines, depth) = self.vla_slot(&u.0)?;
+        lines.extend(vec![
+            "pop rax".into(),
+            format!(
+                "mov rax, [rax+0x{:X}] # sizeof",
+                (depth + 1) * IDENTITY_OFFSET
+            ),
+            format!("imul rax, 0x{:X}", item),
+            PUSH_VAL.into(),
+        ]);
+        Ok(lines)
+    }
     fn mul(&mut self, m: &Typed<Mul>, is_rvar: bool) -> GenResult {
         if !is_rvar && !m.0.is_lvar() {
             return Err(vec![LEFT_VALUE_IS_NOT_ASSIGNABLE.into()]);
         }
-        let first = self.unary(&m.0.first, is_rvar);
-        if first.is_err() {
-            return first;
-        }
-        if m.0.unarys.len() == 0 {
-            return first;
+        let mut lines = self.unary(&m.0.first, is_rvar)?;
+        if m.0.unarys.is_empty() {
+            return Ok(lines);
         }
-        let mut lines = first.unwrap();
+        let mut t = m.0.first.1.clone();
         for u in m.0.unarys.iter() {
             let ope = u.0.ope();
             if ope.is_none() {
                 return Err(vec!["operator expected".into()]);
             }
-            let second = self.unary(u, is_rvar);
-            if second.is_err() {
-                return second;
+            lines.extend(self.unary(u, is_rvar)?);
+            let common = t.common(&u.1);
+            if let Type::LongDouble = common {
+                lines.extend(self.x87_operands(&t, &u.1));
+                let op = if let MulDiv::Multi = ope.as_ref().unwrap() {
+                    "fmulp"
+                } else {
+                    "fdivp"
+                };
+                lines.push(format!("{} st(1), st", op));
+                lines.extend(self.x87_store(&common));
+                t = common;
+                continue;
+            }
+            lines.extend(self.pop_operands(&t, &u.1, &common));
+            t = common;
+            if t.is_float() {
+                let op = if let MulDiv::Multi = ope.as_ref().unwrap() {
+                    "mul"
+                } else {
+                    "div"
+                };
+                lines.extend(float_op(&t, op));
+                lines.push("push rax".into());
+                continue;
             }
-            lines.extend(second.unwrap());
-            lines.push("pop rdi".into());
-            lines.push("pop rax".into());
             match ope.as_ref().unwrap() {
                 MulDiv::Multi => {
                     lines.push("imul rax,rdi".into());
                 }
-                MulDiv::Divide => {
+                MulDiv::Divide if t.is_signed() => {
                     lines.push("cqo".into());
-                    lines.push("idiv rax,rdi".into());
+                    lines.push("idiv rdi".into());
+                }
+                MulDiv::Divide => {
+                    lines.push("xor edx, edx".into());
+                    lines.push("div rdi".into());
                 }
             }
+            lines.extend(extend(&t, &Register::_Ax));
             lines.push("push rax".into());
         }
-        return Ok(lines);
+        Ok(lines)
     }
     fn add(&mut self, a: &Typed<Add>, is_rvar: bool) -> GenResult {
         if !is_rvar && !a.0.is_lvar() {
             return Err(vec![LEFT_VALUE_IS_NOT_ASSIGNABLE.into()]);
         }
-        let first = self.mul(&a.0.first, is_rvar);
-        if first.is_err() {
-            return first;
-        }
-        if a.0.muls.len() == 0 {
-            return first;
+        let mut lines = self.mul(&a.0.first, is_rvar)?;
+        if a.0.muls.is_empty() {
+            return Ok(lines);
         }
-        let mut lines = first.unwrap();
+        // 配列は先頭要素へのポインタとして加減算する
+        let mut t = a.0.first.1.decay();
         for m in a.0.muls.iter() {
             if m.0.ope.is_none() {
                 return Err(vec!["operator expected".into()]);
             }
-            let second = self.mul(m, is_rvar);
-            if second.is_err() {
-                return second;
+            lines.extend(self.mul(m, is_rvar)?);
+            let r = m.1.decay();
+            let ptr_diff = t.is_pointer() && r.is_pointer();
+            let int_only = !t.is_pointer() && !r.is_pointer();
+            if int_only && t.common(&r) == Type::LongDouble {
+                lines.extend(self.x87_operands(&t, &r));
+                let op = if let AddSub::Plus = m.0.ope.as_ref().unwrap() {
+                    "faddp"
+                } else {
+                    "fsubp"
+                };
+                lines.push(format!("{} st(1), st", op));
+                t = Type::LongDouble;
+                lines.extend(self.x87_store(&t));
+                continue;
+            }
+            if int_only {
+                let common = t.common(&r);
+                lines.extend(self.pop_operands(&t, &r, &common));
+                t = common;
+            } else {
+                lines.push("pop rdi".into());
+                lines.push("pop rax".into());
+            }
+            // ポインタと整数の加減算では、整数の側を指す先の大きさ倍する
+            match (t.is_pointer(), r.is_pointer()) {
+                (true, false) => lines.extend(t.when_addsub("rdi".into())),
+                (false, true) => {
+                    lines.extend(r.when_addsub("rax".into()));
+                    t = r.clone();
+                }
+                _ => {}
             }
-            lines.extend(second.unwrap());
-            lines.push("pop rdi".into());
-            lines.extend(a.1.when_addsub("rdi".into())); // FIXME: 型によって加減算のルールを指定したい。とても場当たり的なコード
-            lines.push("pop rax".into());
             match m.0.ope.as_ref().unwrap() {
+                AddSub::Plus if t.is_float() => lines.extend(float_op(&t, "add")),
+                AddSub::Minus if t.is_float() => lines.extend(float_op(&t, "sub")),
                 AddSub::Plus => {
                     lines.push("add rax, rdi".into());
                 }
@@ -279,79 +1495,147 @@ impl Generator<'_> {
                     lines.push("sub rax, rdi".into());
                 }
             }
+            if ptr_diff {
+                // ポインタ同士の差は要素数で表す
+                lines.extend(vec![
+                    "cqo".into(),
+                    format!("mov rdi, {}", t.deref().unwrap().sizeof()),
+                    "idiv rdi".into(),
+                ]);
+                t = Type::Long;
+            }
+            if int_only && !t.is_float() {
+                lines.extend(extend(&t, &Register::_Ax));
+            }
             lines.push("push rax".into());
         }
-        return Ok(lines);
+        Ok(lines)
     }
     fn relational(&mut self, rel: &Typed<Relational>, is_rvar: bool) -> GenResult {
         if !is_rvar && !rel.0.is_lvar() {
             return Err(vec![LEFT_VALUE_IS_NOT_ASSIGNABLE.into()]);
         }
-        let first = self.add(&rel.0.first, is_rvar);
-        if first.is_err() {
-            return first;
-        }
-        if rel.0.adds.len() == 0 {
-            return first;
+        let mut lines = self.add(&rel.0.first, is_rvar)?;
+        if rel.0.adds.is_empty() {
+            return Ok(lines);
         }
-        let mut lines = first.unwrap();
+        // 比較の結果はintなので、続く比較の左辺はintになる
+        let mut t = rel.0.first.1.decay();
         for a in rel.0.adds.iter() {
             if a.0.ope.is_none() {
                 return Err(vec!["operator expected".into()]);
             }
-            let second = self.add(a, is_rvar);
-            if second.is_err() {
-                return second;
+            lines.extend(self.add(a, is_rvar)?);
+            let common = t.common(&a.1.decay());
+            if let Type::LongDouble = common {
+                // st0とst1の比較はucomisと同じフラグになる
+                lines.extend(self.x87_operands(&t, &a.1));
+                let (swap, set) = match a.0.ope.as_ref().unwrap() {
+                    Compare::Lt => (false, "seta"),
+                    Compare::Lte => (false, "setae"),
+                    Compare::Gt => (true, "seta"),
+                    Compare::Gte => (true, "setae"),
+                };
+                if swap {
+                    lines.push("fxch st(1)".into());
+                }
+                lines.push("fucomip st, st(1)".into());
+                lines.push("fstp st(0)".into());
+                lines.push(format!("{} al", set));
+                lines.push("movzb rax, al".into());
+                lines.push("push rax".into());
+                t = Type::Int;
+                continue;
             }
-            lines.extend(second.unwrap());
-            lines.push("pop rdi".into());
-            lines.push("pop rax".into());
-            //LInt型なのが問題？
-            let ax = register(a.1.sizeof(), &Register::_Ax);
-            let di = register(a.1.sizeof(), &Register::Di);
+            lines.extend(self.pop_operands(&t, &a.1, &common));
+            if common.is_float() {
+                // ucomisの結果は符号なしの比較と同じフラグになる。NaNとの比較はすべて偽になる
+                lines.extend(vec![to_xmm(&Register::_Ax), to_xmm(&Register::Di)]);
+                let (cmp, set) = match a.0.ope.as_ref().unwrap() {
+                    Compare::Lt => ("xmm1, xmm0", "seta"),
+                    Compare::Lte => ("xmm1, xmm0", "setae"),
+                    Compare::Gt => ("xmm0, xmm1", "seta"),
+                    Compare::Gte => ("xmm0, xmm1", "setae"),
+                };
+                lines.push(format!("{} {}", sse(&common, "ucomi"), cmp));
+                lines.push(format!("{} al", set));
+                lines.push("movzb rax, al".into());
+                lines.push("push rax".into());
+                t = Type::Int;
+                continue;
+            }
+            // 両辺とも型に合わせて64bitへ拡張済みなので、符号の有無で比較の仕方だけを変える
+            let (lt, le) = if common.is_signed() {
+                ("setl", "setle")
+            } else {
+                ("setb", "setbe")
+            };
             match a.0.ope.as_ref().unwrap() {
                 Compare::Lt => {
-                    lines.push(format!("cmp {}, {}", ax, di));
-                    lines.push("setl al".into());
+                    lines.push("cmp rax, rdi".into());
+                    lines.push(format!("{} al", lt));
                 }
                 Compare::Lte => {
-                    lines.push(format!("cmp {}, {}", ax, di));
-                    lines.push("setle al".into());
+                    lines.push("cmp rax, rdi".into());
+                    lines.push(format!("{} al", le));
                 }
                 Compare::Gt => {
-                    lines.push(format!("cmp {}, {}", di, ax));
-                    lines.push("setl al".into());
+                    lines.push("cmp rdi, rax".into());
+                    lines.push(format!("{} al", lt));
                 }
                 Compare::Gte => {
-                    lines.push(format!("cmp {}, {}", di, ax));
-                    lines.push("setle al".into());
+                    lines.push("cmp rdi, rax".into());
+                    lines.push(format!("{} al", le));
                 }
             }
             lines.push("movzb rax, al".into());
             lines.push("push rax".into());
+            t = Type::Int;
         }
-        return Ok(lines);
+        Ok(lines)
     }
     fn equality(&mut self, eq: &Typed<Equality>, is_rvar: bool) -> GenResult {
         if !is_rvar && !eq.0.is_lvar() {
             return Err(vec![LEFT_VALUE_IS_NOT_ASSIGNABLE.into()]);
         }
         let mut lines = self.relational(&eq.0.first, is_rvar)?;
-        if eq.0.relationals.len() == 0 {
+        if eq.0.relationals.is_empty() {
             return Ok(lines);
         }
+        let mut t = eq.0.first.1.decay();
         for rel in eq.0.relationals.iter() {
             if rel.0.ope.is_none() {
                 return Err(vec!["operator expected".into()]);
             }
-            let second = self.relational(&rel, is_rvar);
-            if second.is_err() {
-                return second;
-            }
             let ope = rel.0.ope.as_ref().unwrap();
-            lines.append(second.unwrap().as_mut());
-            lines.push("pop rdi".into());
-            lines.push("pop rax".into());
+            lines.extend(self.relational(rel, is_rvar)?);
+            let common = t.common(&rel.1.decay());
+            if let Type::LongDouble = common {
+                lines.extend(self.x87_operands(&t, &rel.1));
+                lines.push("fucomip st, st(1)".into());
+                lines.push("fstp st(0)".into());
+            } else {
+                lines.extend(self.pop_operands(&t, &rel.1, &common));
+            }
+            t = Type::Int;
+            if common.is_float() {
+                // NaNはどの値とも等しくない。比較できなければPFが立つ
+                if common != Type::LongDouble {
+                    lines.extend(vec![to_xmm(&Register::_Ax), to_xmm(&Register::Di)]);
+                    lines.push(format!("{} xmm0, xmm1", sse(&common, "ucomi")));
+                }
+                lines.extend(
+                    match ope {
+                        Equals::Equal => ["sete al", "setnp cl", "and al, cl"],
+                        Equals::NotEqual => ["setne al", "setp cl", "or al, cl"],
+                    }
+                    .iter()
+                    .map(|s| s.to_string()),
+                );
+                lines.push("movzb rax, al".into());
+                lines.push("push rax".into());
+                continue;
+            }
             lines.push("cmp rax, rdi".into());
             match ope {
                 Equals::Equal => lines.push("sete al".into()),
@@ -360,44 +1644,125 @@ impl Generator<'_> {
             lines.push("movzb rax, al".into());
             lines.push("push rax".into());
         }
-        return Ok(lines);
+        Ok(lines)
     }
 
     fn assign(&mut self, a: &Typed<&Assign>) -> GenResult {
-        return match a {
+        match a {
             (Assign::Rv(r), _) => self.equality(&r.eq, true),
             (Assign::Asgn(a), _) => {
                 let l = self.equality(&a.lvar, false)?;
+                // 書き込む幅は代入先の型で決まる
+                let t = &a.lvar.1;
 
                 let mut r = self.expr(&(&a.rvar.0, a.rvar.1.clone()))?;
+                self.mark_init(a.lvar.0.primary());
+                // 式の値は代入先の型に変換した値になる
+                r.extend(self.convert_top(&a.rvar.1, t));
                 r.extend(l);
-                r.extend(vec![
-                    "pop rax".into(),
-                    "pop rdi".into(),
-                    format!(
-                        "mov {}[rax], {} # {:?}",
-                        size_directive(&a.rvar.1),
-                        register(a.rvar.1.sizeof(), &Register::Di),
-                        a.rvar.1.clone()
-                    ),
-                    "push rdi".into(),
-                ]);
+                if let Type::Struct(_) = t {
+                    // 構造体の代入はメンバごとのコピー。式の値は代入先のアドレスになる
+                    r.extend(vec!["pop rax".into(), "pop rdi".into()]);
+                    r.extend(copy_memory(t.sizeof()));
+                    r.push(PUSH_VAL.into());
+                    return Ok(r);
+                }
+                if let Some((bt, bf)) = a.lvar.0.unary().and_then(|u| u.bit_field()) {
+                    // ビットフィールドでは、式の値は書き込んだ後のフィールドの値になる
+                    r.push("pop rax".into());
+                    r.extend(store_bits(bt, bf));
+                    r.push("push rdi".into());
+                    return Ok(r);
+                }
+                if let Type::LongDouble = t {
+                    // 値はスタックに残したまま書き込む
+                    r.push("pop rax".into());
+                    r.extend(store_ldouble());
+                    return Ok(r);
+                }
+                r.extend(vec!["pop rax".into(), "pop rdi".into()]);
+                r.push(format!(
+                    "mov {}[rax], {} # {:?}",
+                    size_directive(t),
+                    register(t.sizeof(), &Register::Di),
+                    t
+                ));
+                r.push("push rdi".into());
                 Ok(r)
             }
-        };
+        }
     }
-    fn vardef(&mut self, v: &VarDef) -> GenResult {
-        if v._arrs.len() == 0 {
-            return Ok(vec![
-                "mov rax, rbp".into(),
-                format!("sub rax, {}", v.offset),
-                format!(
-                    "mov {}[rax], {}",
-                    size_directive(&v.type_),
-                    register(v.type_.sizeof(), &Register::Di)
-                ),
-            ]);
+    // 初期化子の無いスカラのローカル変数を、代入されるまで覚えておく
+    fn declare_uninit(&mut self, v: &VarDef) {
+        if self.opts.warn_uninitialized && !matches!(v.type_, Type::Array(_) | Type::Struct(_)) {
+            self.uninit.vars.insert(v.offset, v.ident.clone());
         }
+    }
+    fn mark_init(&mut self, p: Option<&Primary>) {
+        if let Some(PrimaryNode::Lv(Lvar::Id(i))) = p.map(|p| &p.node.0)
+            && self.uninit.vars.contains_key(&i.offset)
+            && let Some(assigned) = &mut self.uninit.assigned
+        {
+            assigned.insert(i.offset);
+        }
+    }
+    // 読み出しはソース上の順序で調べ、同じ変数については一度だけ覚える。到達しない位置の読み出しは数えない
+    fn warn_uninit(&mut self, offset: usize) {
+        if let Some(name) = self.uninit.vars.get(&offset)
+            && self
+                .uninit
+                .assigned
+                .as_ref()
+                .is_some_and(|a| !a.contains(&offset))
+            && !self.uninit.reads.iter().any(|r| r.0 == offset)
+        {
+            self.uninit.reads.push((offset, name.clone()));
+        }
+    }
+    // ラベルへは直前の文からと、そこへ飛ぶすべてのgotoから合流する
+    fn label_uninit(&mut self, ident: &str) {
+        let assigned = meet(self.uninit.assigned.take(), self.uninit.gotos.get(ident));
+        self.uninit.assigned = meet(assigned, self.uninit.back.get(ident));
+    }
+    // gotoの後ろへは制御が流れない
+    fn goto_uninit(&mut self, ident: &str) {
+        if let Some(assigned) = self.uninit.assigned.take() {
+            let joined = meet(Some(assigned), self.uninit.gotos.get(ident));
+            self.uninit.gotos.insert(ident.to_string(), joined.unwrap());
+        }
+    }
+    // 後ろからのgotoはラベルより後で分かるので、ラベルでの状態が変わらなくなるまで本体を調べ直す。
+    // 調べ直す間に振ったラベルと定数は捨てる
+    fn body(&mut self, fimpl: &Block) -> GenResult {
+        let (jump_count, strs, floats) = (self.jump_count, self.strs.len(), self.floats.len());
+        let mut back = HashMap::new();
+        loop {
+            self.uninit = Uninit {
+                assigned: Some(HashSet::new()),
+                back,
+                ..Default::default()
+            };
+            let lines = self.block(fimpl)?;
+            if !self.opts.warn_uninitialized || self.uninit.gotos == self.uninit.back {
+                return Ok(lines);
+            }
+            back = std::mem::take(&mut self.uninit.gotos);
+            self.jump_count = jump_count;
+            self.strs.truncate(strs);
+            self.floats.truncate(floats);
+        }
+    }
+    // 関数の末尾で警告する
+    fn flush_uninit(&mut self) {
+        for (_, name) in std::mem::take(&mut self.uninit.reads) {
+            eprintln!(
+                "warning: '{}' may be used uninitialized in function '{}'",
+                name, self.fident
+            );
+        }
+    }
+    // 可変長配列の領域を実行時に確保する
+    fn vla_def(&mut self, v: &VarDef) -> GenResult {
         //r15に配列全体のバイト数を持っておく
         let mut lines = vec![format!("mov r15, 0x{} # arr def start", 1)];
         let len = v._arrs.len();
@@ -412,33 +1777,80 @@ impl Generator<'_> {
                 "mov [rax], r15".into(),
             ])
         }
-        // 配列自体を指すポイントを確保
+        // 配列全体のメモリをスタックに確保し、その先頭を指すポインタをスロットに置く
+        lines.push(format!("imul r15, 0x{:X}", v.type_.sizeof_item()));
+        lines.push("sub rsp, r15".into());
+        lines.push("and rsp, -16".into());
         lines.push("mov rax, rbp # arr ptr".into());
         lines.push(format!("sub rax, 0x{:X}", v.offset));
-        lines.push("sub rsp, 0x8".into()); // TODO 多分rsp無意味に押し下げすぎ。
         lines.push("mov [rax], rsp".into());
-        lines.push(format!("imul r15, 0x{:X}", v.type_.sizeof_item()));
-        lines.push("sub rsp, r15".into()); // 配列全体のメモリを確保
-        lines.push("sub rsp, 0x8".into());
         lines.push("mov r15, 0x0".into()); // r15後片付け
         Ok(lines)
     }
+    // 宣言子1つ分の初期化。初期化子の無い変数は初期化しない
+    fn var_init(&mut self, v: &VarDef, init: &Option<Initializer>) -> GenResult {
+        match init {
+            None if !v._arrs.is_empty() => self.vla_def(v),
+            None => {
+                self.declare_uninit(v);
+                Ok(vec![])
+            }
+            Some(Initializer::List(elems)) => self.init_list(v, elems),
+            Some(Initializer::Assign(a)) => {
+                let mut lines = self.assign(&(a, a.type_()))?;
+                lines.extend(self.convert_top(&a.type_(), &v.type_));
+                lines.extend(vec![
+                    "mov rax, rbp".into(),
+                    format!("sub rax, {}", v.offset),
+                ]);
+                lines.extend(store_top(&v.type_));
+                Ok(lines)
+            }
+        }
+    }
+    // 初期化子のリストで変数を初期化する。全体を0で埋めてから、書かれた要素を書き込む
+    fn init_list(&mut self, v: &VarDef, elems: &[InitElem]) -> GenResult {
+        let mut lines = vec![
+            "mov rdi, rbp".into(),
+            format!("sub rdi, {}", v.offset),
+            format!("mov rcx, {}", v.type_.sizeof()),
+            "xor eax, eax".into(),
+            "rep stosb".into(),
+        ];
+        for e in elems.iter() {
+            let addr = vec![
+                "mov rax, rbp".into(),
+                format!("sub rax, {}", v.offset - e.offset),
+            ];
+            match &e.value {
+                InitValue::Str(s) => {
+                    lines.extend(addr);
+                    for (i, b) in s.iter().enumerate().filter(|(_, b)| **b != 0) {
+                        lines.push(format!("mov BYTE PTR [rax+0x{:X}], {}", i, b));
+                    }
+                }
+                InitValue::Expr(a) => {
+                    lines.extend(self.assign(&(&a.0, a.1.clone()))?);
+                    let to = if let Type::Bits(b) = &e.type_ {
+                        &b.0
+                    } else {
+                        &e.type_
+                    };
+                    lines.extend(self.convert_top(&a.1, to));
+                    lines.extend(addr);
+                    lines.extend(store_top(&e.type_));
+                }
+            }
+        }
+        Ok(lines)
+    }
     fn expr(&mut self, e: &Typed<&Expr>) -> GenResult {
         match e {
             (Expr::Asgn(ea), _) => self.assign(&(&ea.assign, e.1.clone())),
-            (Expr::VarAsgn(def, assign), _) => {
+            (Expr::VarAsgn(defs), _) => {
                 let mut l = vec![];
-                //0で初期化
-                if assign.is_none() {
-                    l.push("push 0".into());
-                } else {
-                    l.extend(
-                        self.assign(&(assign.as_ref().unwrap(), assign.as_ref().unwrap().type_()))?,
-                    );
-                };
-                l.push("pop rdi".into());
-                for v in def.iter() {
-                    l.extend(self.vardef(v)?);
+                for (v, init) in defs.iter() {
+                    l.extend(self.var_init(v, init)?);
                 }
                 Ok(l)
             }
@@ -449,26 +1861,26 @@ impl Generator<'_> {
             None => vec![],
             Some(e) => self.expr(&(&e.0, e.1.clone()))?,
         };
-        let cond = match &f.cond {
-            None => vec![],
-            Some(e) => self.expr(&(&e.0, e.1.clone()))?,
+        let (cond, cond_type) = match &f.cond {
+            None => (vec![], Type::Int),
+            Some(e) => (self.expr(&(&e.0, e.1.clone()))?, e.1.clone()),
         };
+        // 本体は一度も実行されないことがあるので、本体での代入はループの後ろでは代入済みにならない
+        let assigned = self.uninit.assigned.clone();
+        let stmt = self.stmt(&f.stmt)?;
         let step = match &f.step {
             None => vec![],
             Some(e) => self.expr(&(&e.0, e.1.clone()))?,
         };
-        let stmt = self.stmt(&f.stmt)?;
+        self.uninit.assigned = assigned;
         let start_label = format!(".ForStart{}", self.jump_label());
         let end_label = format!(".EndStart{}", self.jump_label());
         Ok([
             init,
             vec![start_label.clone() + ":"],
             cond,
-            vec![
-                "pop rax".into(),
-                "cmp rax, 0".into(),
-                "je ".to_string() + &end_label,
-            ],
+            test_cond(&cond_type),
+            vec!["je ".to_string() + &end_label],
             stmt,
             step,
             vec!["jmp ".to_string() + &start_label, end_label + ":"],
@@ -479,15 +1891,14 @@ impl Generator<'_> {
         let cond = self.expr(&(&w.cond.0, w.cond.1.clone()))?;
         let start_label = format!(".WhileStart{}", self.jump_label());
         let end_label = format!(".WhileEnd{}", self.jump_label());
+        let assigned = self.uninit.assigned.clone();
         let stmt = self.stmt(&w.stmt)?;
+        self.uninit.assigned = assigned;
         Ok([
             vec![start_label.clone() + ":"],
             cond,
-            vec![
-                "pop rax".into(),
-                "cmp rax, 0".into(),
-                "je ".to_string() + &end_label,
-            ],
+            test_cond(&w.cond.1),
+            vec!["je ".to_string() + &end_label],
             stmt,
             vec!["jmp ".to_string() + &start_label, end_label + ":"],
         ]
@@ -496,29 +1907,27 @@ impl Generator<'_> {
     fn if_(&mut self, i: &If) -> GenResult {
         let cond = self.expr(&(&i.cond.0, i.cond.1.clone()))?;
         let end_label = format!(".IfEnd{}", self.jump_label());
+        let assigned = self.uninit.assigned.clone();
         let stmt = self.stmt(&i.stmt)?;
+        let then = std::mem::replace(&mut self.uninit.assigned, assigned.clone());
         if i.else_.is_none() {
+            self.uninit.assigned = meet(then, assigned.as_ref());
             return Ok([
                 cond,
-                vec![
-                    "pop rax".into(),
-                    "cmp rax, 0".into(),
-                    "je ".to_string() + &end_label,
-                ],
+                test_cond(&i.cond.1),
+                vec!["je ".to_string() + &end_label],
                 stmt,
                 vec![end_label + ":"],
             ]
             .concat());
         }
         let else_ = self.stmt(i.else_.as_ref().unwrap())?;
+        self.uninit.assigned = meet(then, self.uninit.assigned.as_ref());
         let else_label = format!(".IfElse{}", self.jump_label());
         Ok([
             cond,
-            vec![
-                "pop rax".into(),
-                "cmp rax, 0".into(),
-                "je ".to_string() + &else_label,
-            ],
+            test_cond(&i.cond.1),
+            vec!["je ".to_string() + &else_label],
             stmt,
             vec!["jmp ".to_string() + &end_label, else_label + ":"],
             else_,
@@ -536,18 +1945,110 @@ impl Generator<'_> {
                 .stmts
                 .iter()
                 .map(|f| self.stmt(f))
-                .reduce(|a, b| concat(a, b))
+                .reduce(concat)
                 .unwrap_or(Ok(vec![])),
+            Statement::Label(l) => {
+                self.label_uninit(&l.ident);
+                let label = self.user_label(&l.ident);
+                Ok([vec![label + ":"], self.stmt(&l.stmt)?].concat())
+            }
+            Statement::Goto(ident) => {
+                self.goto_uninit(ident);
+                Ok(vec![format!("jmp {}", self.user_label(ident))])
+            }
+            Statement::Asm(a) => self.asm(a),
             Statement::Stmt(s) => {
                 let lines = self.expr(&(&s.expr.0, s.expr.1.clone()))?;
                 if s.expr.0.does_return() {
-                    Ok([lines, self.epilogue()?].concat())
+                    // 戻り値は関数の戻り値の型に変換する
+                    let ret = self.ret.clone();
+                    let conv = self.convert_top(&s.expr.1, &ret);
+                    self.uninit.assigned = None;
+                    Ok([lines, conv, self.epilogue()?].concat())
                 } else {
                     Ok(lines)
                 }
             }
         }
     }
+    // 出力のアドレスと入力の値を積んでからレジスタに割り当て、テンプレートを出力した後で出力を書き戻す
+    fn asm(&mut self, a: &Asm) -> GenResult {
+        let operands: Vec<&AsmOperand> = a.outputs.iter().chain(a.inputs.iter()).collect();
+        let regs = asm_registers(a)?;
+        // 呼び出し先で保存すべきレジスタを壊すなら、前後で退避する
+        let saved: Vec<&str> = a.clobbers.iter().filter_map(|c| callee_saved(c)).collect();
+        let mut lines: Vec<String> = saved.iter().map(|r| format!("push {}", r)).collect();
+        for o in a.outputs.iter() {
+            lines.extend(self.equality(&o.expr, false)?);
+            self.mark_init(o.expr.0.primary());
+        }
+        for o in a.inputs.iter() {
+            match o.constraint {
+                'i' => {}
+                'm' => lines.extend(self.equality(&o.expr, false)?),
+                _ => lines.extend(self.equality(&o.expr, true)?),
+            }
+        }
+        for reg in regs[a.outputs.len()..].iter().rev().flatten() {
+            lines.push(format!("pop {}", register(8, reg)));
+        }
+        // 出力のアドレスはスタックに残したまま、メモリなら参照先として、+なら値を読んでおく
+        let count = a.outputs.len();
+        for (i, (o, reg)) in a.outputs.iter().zip(regs.iter()).enumerate() {
+            let reg = reg.unwrap();
+            let slot = format!("[rsp+{}]", (count - 1 - i) * IDENTITY_OFFSET);
+            if o.constraint == 'm' {
+                lines.push(format!("mov {}, {}", register(8, &reg), slot));
+            } else if o.read {
+                lines.push(format!("mov {}, {}", register(8, &reg), slot));
+                lines.push(load_zx(o.expr.1.sizeof(), &reg, &register(8, &reg)));
+            }
+        }
+        let texts: Vec<String> = operands
+            .iter()
+            .zip(regs.iter())
+            .map(|(o, reg)| match (o.constraint, reg) {
+                ('i', _) => o.expr.0.eval().unwrap().to_string(),
+                ('m', Some(reg)) => {
+                    format!("{}[{}]", ptr_directive(o.expr.1.sizeof()), register(8, reg))
+                }
+                (_, Some(reg)) => register(o.expr.1.sizeof(), reg),
+                _ => String::new(),
+            })
+            .collect();
+        let text = a.expand(&texts).unwrap();
+        lines.extend(
+            text.lines()
+                .map(|l| l.trim().to_string())
+                .filter(|l| !l.is_empty()),
+        );
+        // レジスタに置いた出力の値を積み、アドレスと組にして書き込む
+        for reg in regs[..count].iter() {
+            lines.push(format!("push {}", register(8, &reg.unwrap())));
+        }
+        for (i, o) in a
+            .outputs
+            .iter()
+            .enumerate()
+            .filter(|(_, o)| o.constraint != 'm')
+        {
+            let t = &o.expr.1;
+            lines.extend(vec![
+                format!("mov rax, [rsp+{}]", (2 * count - 1 - i) * IDENTITY_OFFSET),
+                format!("mov rdi, [rsp+{}]", (count - 1 - i) * IDENTITY_OFFSET),
+                format!(
+                    "mov {}[rax], {}",
+                    size_directive(t),
+                    register(t.sizeof(), &Register::Di)
+                ),
+            ]);
+        }
+        if count > 0 {
+            lines.push(format!("add rsp, {}", 2 * count * IDENTITY_OFFSET));
+        }
+        lines.extend(saved.iter().rev().map(|r| format!("pop {}", r)));
+        Ok(lines)
+    }
     fn block(&mut self, b: &Block) -> GenResult {
         let mut lines = Vec::new();
         for s in b.stmts.iter() {
@@ -558,30 +2059,81 @@ impl Generator<'_> {
     }
     fn prologue(&mut self, f: &Fdef) -> GenResult {
         // 引数を頭から順に入れたらstackには逆順に入っているはず
-        let args: Vec<Vec<String>> = f
+        let types: Vec<Type> = f.args.iter().map(|a| a.type_.clone()).collect();
+        let hidden = returns_in_memory(&f.ret);
+        let mut locs: Vec<(&VarDef, ArgLoc)> = f
             .args
             .iter()
-            .enumerate()
-            .map(|(i, a)| {
-                // 6つまではレジスタ経由。rdi,rsi,rdx,rx,r8,r9の順。
-                if i < FARG_REGS.len() {
-                    let r = FARG_REGS.iter().nth(i).unwrap();
+            .zip(classify(&types, hidden as usize))
+            .collect();
+        // スタック渡しの引数はrdiを経由して移すので、レジスタ渡しの引数を退避し終えてから移す
+        locs.sort_by_key(|(_, loc)| matches!(loc, ArgLoc::Stack(_)));
+        let args: Vec<Vec<String>> = locs
+            .into_iter()
+            .map(|(a, loc)| match loc {
+                // 整数は6つまでrdi,rsi,rdx,rx,r8,r9の順にレジスタ経由。
+                ArgLoc::Gp(n) => vec![
+                    "mov rax, rbp".into(),
+                    format!("sub rax, {}", a.offset),
+                    format!(
+                        "mov {}[rax], {}",
+                        size_directive(&a.type_),
+                        register(a.type_.sizeof(), &FARG_REGS[n])
+                    ),
+                ],
+                // 浮動小数点数は8つまでxmm0からxmm7
+                ArgLoc::Sse(n) => vec![
+                    "mov rax, rbp".into(),
+                    format!("sub rax, {}", a.offset),
+                    format!(
+                        "{} {}[rax], xmm{}",
+                        sse(&a.type_, "mov"),
+                        size_directive(&a.type_),
+                        n
+                    ),
+                ],
+                // 構造体は8byteごとにレジスタから書き出す
+                ArgLoc::Struct(regs) => {
+                    let mut lines = vec!["mov rax, rbp".into(), format!("sub rax, {}", a.offset)];
+                    for (i, loc) in regs.iter().enumerate() {
+                        let offset = i * IDENTITY_OFFSET;
+                        let width = (a.type_.sizeof() - offset).min(IDENTITY_OFFSET);
+                        let reg = match loc {
+                            ArgLoc::Sse(n) => {
+                                lines.push(format!("movq r11, xmm{}", n));
+                                &Register::_11
+                            }
+                            ArgLoc::Gp(n) => &FARG_REGS[*n],
+                            _ => continue,
+                        };
+                        lines.extend(store_bytes(reg, "rax", offset, width));
+                    }
+                    lines
+                }
+                // 残りの引数はリターンアドレスの上に並んでいる
+                ArgLoc::Stack(k) if matches!(a.type_, Type::Struct(_)) => [
                     vec![
+                        "mov rdi, rbp".into(),
+                        format!("add rdi, {}", k + 2 * IDENTITY_OFFSET),
                         "mov rax, rbp".into(),
                         format!("sub rax, {}", a.offset),
-                        format!(
-                            "mov {}[rax], {}",
-                            size_directive(&a.type_),
-                            register(a.type_.sizeof(), r)
-                        ),
-                    ]
-                } else {
-                    // 7つ目以降の引数はrbpの前に逆順で積まれている
-                    let offset = (i + 1 - FARG_REGS.len()) * IDENTITY_OFFSET;
+                    ],
+                    copy_memory(a.type_.sizeof()),
+                ]
+                .concat(),
+                ArgLoc::Stack(k) if a.type_ == Type::LongDouble => vec![
+                    "mov rax, rbp".into(),
+                    format!("sub rax, {}", a.offset),
+                    format!("mov rdi, [rbp+{}]", k + 2 * IDENTITY_OFFSET),
+                    "mov [rax], rdi".into(),
+                    format!("mov rdi, [rbp+{}]", k + 3 * IDENTITY_OFFSET),
+                    "mov [rax+8], rdi".into(),
+                ],
+                ArgLoc::Stack(k) => {
                     let sd = size_directive(&a.type_);
                     vec![
                         "mov rax, rbp".into(),
-                        format!("add rax, {}", offset + IDENTITY_OFFSET), // リターンドレスの分で8余分に動かす
+                        format!("add rax, {}", k + 2 * IDENTITY_OFFSET), // 退避したrbpとリターンアドレスの分
                         "mov rdi, [rax]".into(),
                         "mov rax, rbp".into(),
                         format!("sub rax, {}", a.offset),
@@ -594,43 +2146,205 @@ impl Generator<'_> {
                 }
             })
             .collect();
-        Ok(vec![
+        Ok([
             vec![
+                linkage_directive(&f.linkage, &f.ident),
                 format!("{}:", f.ident),
                 "push rbp #prlg ->".into(),
                 "mov rbp, rsp".into(),
                 format!("sub rsp, {} {}", f.required_memory, "#<- prlg"),
             ],
+            // メモリ経由で返す構造体の置き場所はrdiで渡される
+            match f.ret_slot.filter(|_| hidden) {
+                Some(slot) => vec![format!("mov [rbp-{}], rdi", slot)],
+                None => vec![],
+            },
             args.concat(),
         ]
         .concat())
     }
     fn epilogue(&mut self) -> GenResult {
-        Ok(vec![
-            "pop rax #eplg ->".into(),
-            "mov rsp, rbp".into(),
-            "pop rbp".into(),
-            "ret #<- eplg".into(),
-        ])
+        // 浮動小数点数の戻り値はxmm0で返す。long doubleはst0に積んで返す
+        let ret = match &self.ret.unqualified() {
+            Type::LongDouble => vec!["fld TBYTE PTR [rsp] #eplg ->".into()],
+            t @ Type::Struct(_) => self.return_struct(t),
+            t if t.is_float() => vec!["pop rax #eplg ->".into(), to_xmm(&Register::_Ax)],
+            _ => vec!["pop rax #eplg ->".into()],
+        };
+        Ok([ret, leave()].concat())
+    }
+    // 構造体の値はアドレスなので、中身をrax,rdxとxmm0,xmm1に分けて返すか、呼び出し元が渡した置き場所へコピーする
+    fn return_struct(&self, t: &Type) -> Vec<String> {
+        if x87_struct(t) {
+            return vec!["pop rsi #eplg ->".into(), "fld TBYTE PTR [rsi]".into()];
+        }
+        let Some(classes) = classify_struct(t) else {
+            let slot = self.ret_slot.unwrap();
+            return [
+                vec![
+                    "pop rdi #eplg ->".into(),
+                    format!("mov rax, [rbp-{}]", slot),
+                ],
+                copy_memory(t.sizeof()),
+            ]
+            .concat();
+        };
+        let mut lines = vec!["pop rsi #eplg ->".into()];
+        let (mut gp, mut sse) = (0, 0);
+        for (i, class) in classes.iter().enumerate() {
+            let offset = i * IDENTITY_OFFSET;
+            let width = (t.sizeof() - offset).min(IDENTITY_OFFSET);
+            if let Class::Integer = class {
+                gp += 1;
+                let reg = if gp == 1 { Register::_Ax } else { Register::Dx };
+                lines.extend(load_bytes(&reg, "rsi", offset, width));
+            } else {
+                sse += 1;
+                lines.extend(load_bytes(&Register::_11, "rsi", offset, width));
+                lines.push(format!("movq xmm{}, r11", sse - 1));
+            }
+        }
+        lines
     }
     fn fdef(&mut self) -> GenResult {
         let mut genr = Ok(Vec::new());
         for f in self.p.fdefs.iter() {
+            self.fident = f.ident.clone();
+            self.ret = f.ret.clone();
+            self.ret_slot = f.ret_slot;
+            // 構造体を返す関数の末尾に戻り値は積まれていないので、そのまま戻る
+            let end = if let Type::Struct(_) = f.ret.base() {
+                Ok(leave())
+            } else {
+                self.epilogue()
+            };
             genr = concat(
                 genr,
-                concat_multi(&[self.prologue(f), self.block(&f.fimpl), self.epilogue()]),
+                concat_multi(&[self.prologue(f), self.body(&f.fimpl), end]),
             );
+            self.flush_uninit();
         }
         genr
     }
+    fn ginit(&mut self, init: &GInit, t: &Type) -> Vec<String> {
+        match init {
+            GInit::Int(v) => vec![format!("{} {}", data_directive(t.sizeof()), v)],
+            GInit::Str(s) => vec![format!(".quad {}", self.str_label(s))],
+            GInit::Addr(label, 0) => vec![format!(".quad {}", self.sym(label))],
+            GInit::Addr(label, offset) => vec![format!(".quad {}{:+}", self.sym(label), offset)],
+            // 仮数、符号と指数の順に置く。80bitの後ろの6byteは詰め物
+            GInit::LDouble(l) => vec![
+                format!(".quad 0x{:X}", l.mant),
+                format!(".short 0x{:X}", (l.sign as u16) << 15 | l.exp),
+                ".zero 6".into(),
+            ],
+            GInit::Bytes(b) => {
+                let bytes: Vec<String> = b.iter().map(|b| b.to_string()).collect();
+                vec![format!(".byte {}", bytes.join(","))]
+            }
+            // 要素の間と末尾の隙間は0で埋める
+            GInit::List(items) => {
+                let mut lines = vec![];
+                let mut pos = 0;
+                for (offset, t, init) in items.iter() {
+                    if *offset > pos {
+                        lines.push(format!(".zero {}", offset - pos));
+                    }
+                    lines.extend(self.ginit(init, t));
+                    pos = offset + t.sizeof();
+                }
+                if t.sizeof() > pos {
+                    lines.push(format!(".zero {}", t.sizeof() - pos));
+                }
+                lines
+            }
+        }
+    }
+    fn gvars(&mut self) -> GenResult {
+        let mut lines = Vec::new();
+        for g in self.p.gvars.iter().filter(|g| g.defined) {
+            lines.push(gvar_section(g).into());
+            lines.push(linkage_directive(&g.linkage, &g.ident));
+            lines.push(format!(".align {}", g.type_.alignof()));
+            lines.push(format!("{}:", g.ident));
+            lines.extend(match &g.init {
+                Some(init) => self.ginit(init, &g.type_),
+                None => vec![format!(".zero {}", g.type_.sizeof())],
+            });
+        }
+        lines.push(".text".into());
+        Ok(lines)
+    }
+    // ファイルスコープのasmはそのまま出力する。中で切り替えられてもいいように、後で.textに戻す
+    fn toplevel_asm(&self) -> Vec<String> {
+        if self.p.asms.is_empty() {
+            return vec![];
+        }
+        let lines = self
+            .p
+            .asms
+            .iter()
+            .flat_map(|a| a.lines().map(|l| l.trim().to_string()));
+        lines.chain([".text".to_string()]).collect()
+    }
+    fn rodata(&mut self) -> GenResult {
+        let mut lines = vec![".section .rodata".into()];
+        for (i, s) in self.strs.iter().enumerate() {
+            let bytes: Vec<String> = s.iter().chain([0].iter()).map(|b| b.to_string()).collect();
+            lines.push(format!(".LC{}:", i));
+            lines.push(format!(".byte {}", bytes.join(",")));
+        }
+        for (i, (value, size)) in self.floats.iter().enumerate() {
+            lines.push(format!(".align {}", size));
+            lines.push(format!(".LF{}:", i));
+            if *size == 16 {
+                lines.extend(vec![format!(".tfloat {}", value), ".zero 6".into()]);
+            } else {
+                lines.push(format!("{} {}", data_directive(*size), value));
+            }
+        }
+        Ok(lines)
+    }
     fn generate(&mut self) -> GenResult {
-        self.fdef()
+        let lines = concat_multi(&[self.gvars(), Ok(self.toplevel_asm()), self.fdef()])?;
+        let lines = [lines, self.bounds_fail()].concat();
+        let lines = concat(Ok(lines), self.rodata())?;
+        // 別名はAT&Tの記法で定義する
+        let mut prelude = Vec::new();
+        if !self.aliases.is_empty() {
+            prelude.push(".att_syntax".into());
+            prelude.extend(
+                self.aliases
+                    .iter()
+                    .map(|a| format!(".set .Lsym.{}, {}", a, a)),
+            );
+            prelude.push(".intel_syntax noprefix".into());
+        }
+        Ok([prelude, lines].concat())
+    }
+    fn sym(&mut self, name: &str) -> String {
+        if is_asm_reserved(name) {
+            self.aliases.insert(name.into());
+            format!(".Lsym.{}", name)
+        } else {
+            name.into()
+        }
     }
 }
-pub fn generate(p: &Program) -> GenResult {
+pub fn generate(p: &Program, src: &str, opts: &Options) -> GenResult {
     Generator {
-        p: p,
+        p,
         jump_count: 0,
+        fident: String::new(),
+        strs: Vec::new(),
+        floats: Vec::new(),
+        ret: Type::Int,
+        ret_slot: None,
+        src,
+        opts,
+        bounds_fail: false,
+        uninit: Uninit::default(),
+        aliases: BTreeSet::new(),
         _array_size: HashMap::new(),
     }
     .generate()
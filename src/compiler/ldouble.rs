@@ -0,0 +1,423 @@
+use std::{
+    cmp::Ordering,
+    ops::{Add, Div, Mul, Neg, Sub},
+};
+
+// long doubleの値。x87の80bit拡張倍精度と同じ形で持ち、定数式をgccと同じ精度で計算する
+#[derive(Debug, Clone, Copy)]
+pub struct LDouble {
+    pub sign: bool,
+    pub exp: u16,  // バイアス付きの15bitの指数。0なら非正規化数、0x7fffなら無限大かNaN
+    pub mant: u64, // 整数部のビットも明示する64bitの仮数
+}
+const BIAS: i32 = 16383;
+const MAX_EXP: u16 = 0x7fff;
+const INT_BIT: u64 = 1 << 63;
+// 仮数の最下位ビットの重みの下限。非正規化数の最下位ビットにあたる
+const MIN_LSB: i32 = 1 - BIAS - 63;
+
+// m * 2^e をprecビットの仮数へ最近接偶数に丸め、丸めた仮数とその最下位ビットの指数を返す。
+// stickyはmより下に0でないビットがあったこと。最下位ビットの指数はmin_lsbより小さくしない
+fn round(m: u128, e: i32, sticky: bool, prec: u32, min_lsb: i32) -> (u128, i32) {
+    if m == 0 {
+        return (0, min_lsb);
+    }
+    let bits = 128 - m.leading_zeros() as i32;
+    let shift = (bits - prec as i32).max(min_lsb - e);
+    if shift <= 0 {
+        return (m << -shift, e + shift);
+    }
+    let (mut q, up) = if shift >= 128 {
+        // 半分の重みは2^127以上なので、mがちょうどそれを超えるときだけ切り上がる
+        let half = 1u128 << 127;
+        (0, shift == 128 && (m > half || m == half && sticky))
+    } else {
+        let q = m >> shift;
+        let rest = m & ((1 << shift) - 1);
+        let half = 1u128 << (shift - 1);
+        (q, rest > half || rest == half && (sticky || q & 1 == 1))
+    };
+    let mut lsb = e + shift;
+    q += up as u128;
+    // 繰り上がって桁が増えたら1桁落とす。落ちるビットは0
+    if q >> prec != 0 {
+        q >>= 1;
+        lsb += 1;
+    }
+    (q, lsb)
+}
+// 大きな非負整数。10進数の字句を正確に2進数へ直すのに使う
+#[derive(Clone)]
+struct Big(Vec<u32>);
+impl Big {
+    fn bits(&self) -> usize {
+        match self.0.iter().rposition(|l| *l != 0) {
+            Some(i) => i * 32 + 32 - self.0[i].leading_zeros() as usize,
+            None => 0,
+        }
+    }
+    fn bit(&self, i: usize) -> bool {
+        self.0.get(i / 32).is_some_and(|l| l >> (i % 32) & 1 == 1)
+    }
+    fn mul_add(&mut self, mul: u32, add: u32) {
+        let mut carry = add as u64;
+        for l in self.0.iter_mut() {
+            let v = *l as u64 * mul as u64 + carry;
+            *l = v as u32;
+            carry = v >> 32;
+        }
+        if carry != 0 {
+            self.0.push(carry as u32);
+        }
+    }
+    fn pow10(n: usize) -> Big {
+        let mut b = Big(vec![1]);
+        for _ in 0..n {
+            b.mul_add(10, 0);
+        }
+        b
+    }
+    fn shl1(&mut self, bit: bool) {
+        let mut carry = bit as u32;
+        for l in self.0.iter_mut() {
+            let next = *l >> 31;
+            *l = *l << 1 | carry;
+            carry = next;
+        }
+        if carry != 0 {
+            self.0.push(carry);
+        }
+    }
+    fn cmp(&self, o: &Big) -> Ordering {
+        let len = self.0.len().max(o.0.len());
+        (0..len)
+            .rev()
+            .map(|i| self.0.get(i).unwrap_or(&0).cmp(o.0.get(i).unwrap_or(&0)))
+            .find(|o| o.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+    fn sub(&mut self, o: &Big) {
+        let mut borrow = 0i64;
+        for (i, l) in self.0.iter_mut().enumerate() {
+            let v = *l as i64 - *o.0.get(i).unwrap_or(&0) as i64 - borrow;
+            borrow = (v < 0) as i64;
+            *l = v.rem_euclid(1 << 32) as u32;
+        }
+    }
+    // 上位128bitと、その最下位ビットの指数、それより下に0でないビットがあるか
+    fn top(&self) -> (u128, i32, bool) {
+        let bits = self.bits();
+        let low = bits.saturating_sub(128);
+        let m = (low..bits)
+            .rev()
+            .fold(0u128, |m, i| m << 1 | self.bit(i) as u128);
+        (m, low as i32, (0..low).any(|i| self.bit(i)))
+    }
+    // self * 2^shift / d の商の上位128bit。余りがあればstickyに含める
+    fn div_top(&self, shift: usize, d: &Big) -> (u128, i32, bool) {
+        let (mut q, mut r) = (Big(vec![]), Big(vec![]));
+        for i in (0..self.bits() + shift).rev() {
+            r.shl1(i >= shift && self.bit(i - shift));
+            let ge = r.cmp(d).is_ge();
+            if ge {
+                r.sub(d);
+            }
+            q.shl1(ge);
+        }
+        let (m, e, sticky) = q.top();
+        (m, e, sticky || r.bits() != 0)
+    }
+}
+impl LDouble {
+    pub const NAN: LDouble = LDouble {
+        sign: false,
+        exp: MAX_EXP,
+        mant: INT_BIT | INT_BIT >> 1,
+    };
+    fn zero(sign: bool) -> LDouble {
+        LDouble {
+            sign,
+            exp: 0,
+            mant: 0,
+        }
+    }
+    fn inf(sign: bool) -> LDouble {
+        LDouble {
+            sign,
+            exp: MAX_EXP,
+            mant: INT_BIT,
+        }
+    }
+    fn is_nan(self) -> bool {
+        self.exp == MAX_EXP && self.mant != INT_BIT
+    }
+    fn is_inf(self) -> bool {
+        self.exp == MAX_EXP && self.mant == INT_BIT
+    }
+    fn is_zero(self) -> bool {
+        self.exp == 0 && self.mant == 0
+    }
+    // 有限で0でない値を、最上位ビットを立てた仮数mでm * 2^eと表す
+    fn parts(self) -> (u64, i32) {
+        let e = (self.exp as i32).max(1) - BIAS - 63;
+        let z = self.mant.leading_zeros();
+        (self.mant << z, e - z as i32)
+    }
+    // m * 2^eを丸めて値にする
+    fn from_parts(sign: bool, m: u128, e: i32, sticky: bool) -> LDouble {
+        let (q, lsb) = round(m, e, sticky, 64, MIN_LSB);
+        if q == 0 {
+            return LDouble::zero(sign);
+        }
+        let exp = if q >> 63 == 0 { 0 } else { lsb + 63 + BIAS };
+        if exp >= MAX_EXP as i32 {
+            return LDouble::inf(sign);
+        }
+        LDouble {
+            sign,
+            exp: exp as u16,
+            mant: q as u64,
+        }
+    }
+    pub fn from_i64(v: i64) -> LDouble {
+        LDouble::from_parts(v < 0, v.unsigned_abs() as u128, 0, false)
+    }
+    pub fn from_u64(v: u64) -> LDouble {
+        LDouble::from_parts(false, v as u128, 0, false)
+    }
+    pub fn from_f64(v: f64) -> LDouble {
+        let bits = v.to_bits();
+        let sign = bits >> 63 == 1;
+        let exp = (bits >> 52 & 0x7ff) as i32;
+        let frac = bits & ((1 << 52) - 1);
+        match exp {
+            0x7ff if frac == 0 => LDouble::inf(sign),
+            0x7ff => LDouble::NAN,
+            0 => LDouble::from_parts(sign, frac as u128, -1074, false),
+            _ => LDouble::from_parts(sign, (frac | 1 << 52) as u128, exp - 1075, false),
+        }
+    }
+    // 10進数の浮動小数点数の字句。符号は含まない
+    pub fn parse(text: &str) -> Option<LDouble> {
+        let (digits, exp) = match text.split_once(['e', 'E']) {
+            Some((d, e)) => (d, e.parse::<i64>().ok()?),
+            None => (text, 0),
+        };
+        let (int, frac) = digits.split_once('.').unwrap_or((digits, ""));
+        let digits = format!("{}{}", int, frac)
+            .trim_start_matches('0')
+            .to_string();
+        if !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        if digits.is_empty() {
+            return Some(LDouble::zero(false));
+        }
+        let exp = exp - frac.len() as i64;
+        // 10^4933以上は無限大、10^-4952未満は0になる
+        let magnitude = exp + digits.len() as i64;
+        if magnitude > 4934 {
+            return Some(LDouble::inf(false));
+        }
+        if magnitude < -4952 {
+            return Some(LDouble::zero(false));
+        }
+        let mut n = Big(vec![]);
+        for b in digits.bytes() {
+            n.mul_add(10, (b - b'0') as u32);
+        }
+        let (m, e, sticky) = if exp >= 0 {
+            for _ in 0..exp {
+                n.mul_add(10, 0);
+            }
+            n.top()
+        } else {
+            // 商が128bit以上になるよう、割る前に2のべきを掛けておく
+            let d = Big::pow10(-exp as usize);
+            let shift = (128 + d.bits()).saturating_sub(n.bits());
+            let (m, e, sticky) = n.div_top(shift, &d);
+            (m, e - shift as i32, sticky)
+        };
+        Some(LDouble::from_parts(false, m, e, sticky))
+    }
+    // precビットの仮数とbiasの指数を持つ2進浮動小数点数へ丸めて、符号・指数・仮数を返す
+    fn narrow(self, prec: u32, bias: i32, exp_bits: u32) -> u64 {
+        let frac_bits = prec - 1;
+        let max = (1u64 << exp_bits) - 1;
+        let sign = (self.sign as u64) << (exp_bits + frac_bits);
+        if self.is_nan() {
+            return sign | max << frac_bits | 1 << (frac_bits - 1);
+        }
+        if self.is_inf() {
+            return sign | max << frac_bits;
+        }
+        if self.is_zero() {
+            return sign;
+        }
+        let (m, e) = self.parts();
+        let (q, lsb) = round(m as u128, e, false, prec, 1 - bias - frac_bits as i32);
+        let q = q as u64;
+        if q >> frac_bits == 0 {
+            return sign | q;
+        }
+        let exp = (lsb + frac_bits as i32 + bias) as u64;
+        if exp >= max {
+            return sign | max << frac_bits;
+        }
+        sign | exp << frac_bits | q & ((1 << frac_bits) - 1)
+    }
+    pub fn to_f64(self) -> f64 {
+        f64::from_bits(self.narrow(53, 1023, 11))
+    }
+    pub fn to_f32(self) -> f32 {
+        f32::from_bits(self.narrow(24, 127, 8) as u32)
+    }
+    // 小数部を0に向かって切り捨てた整数。範囲外は飽和させ、NaNは0にする
+    pub fn trunc(self) -> i128 {
+        if self.is_nan() || self.is_zero() {
+            return 0;
+        }
+        let v = if self.is_inf() {
+            i128::MAX
+        } else {
+            let (m, e) = self.parts();
+            match e {
+                ..=-64 => 0,
+                -63..0 => (m >> -e) as i128,
+                0..64 => (m as i128) << e,
+                _ => i128::MAX,
+            }
+        };
+        if self.sign { -v } else { v }
+    }
+}
+impl Neg for LDouble {
+    type Output = LDouble;
+    fn neg(self) -> LDouble {
+        LDouble {
+            sign: !self.sign,
+            ..self
+        }
+    }
+}
+impl Add for LDouble {
+    type Output = LDouble;
+    fn add(self, o: LDouble) -> LDouble {
+        if self.is_nan() || o.is_nan() || self.is_inf() && o.is_inf() && self.sign != o.sign {
+            return LDouble::NAN;
+        }
+        if self.is_inf() || o.is_zero() && !self.is_zero() {
+            return self;
+        }
+        if o.is_inf() || self.is_zero() && !o.is_zero() {
+            return o;
+        }
+        if self.is_zero() {
+            return LDouble::zero(self.sign && o.sign);
+        }
+        // 指数の大きい方に揃える。小さい方からはみ出たビットは最下位ビットにまとめれば丸めの結果は変わらない
+        let (a, b) = if self.parts().1 >= o.parts().1 {
+            (self, o)
+        } else {
+            (o, self)
+        };
+        let ((ma, ea), (mb, eb)) = (a.parts(), b.parts());
+        let d = (ea - eb) as u32;
+        let wide_a = (ma as u128) << 62;
+        let wide_b = (mb as u128) << 62;
+        let shifted = wide_b.checked_shr(d).unwrap_or(0);
+        let lost = shifted.checked_shl(d).is_none_or(|s| s != wide_b);
+        let wide_b = shifted | lost as u128;
+        let (sign, m) = match (a.sign == b.sign, wide_a.cmp(&wide_b)) {
+            (true, _) => (a.sign, wide_a + wide_b),
+            (false, Ordering::Less) => (b.sign, wide_b - wide_a),
+            (false, _) => (a.sign, wide_a - wide_b),
+        };
+        // 差がちょうど0なら+0になる
+        if m == 0 {
+            return LDouble::zero(false);
+        }
+        LDouble::from_parts(sign, m, ea - 62, false)
+    }
+}
+impl Sub for LDouble {
+    type Output = LDouble;
+    fn sub(self, o: LDouble) -> LDouble {
+        self + -o
+    }
+}
+impl Mul for LDouble {
+    type Output = LDouble;
+    fn mul(self, o: LDouble) -> LDouble {
+        let sign = self.sign != o.sign;
+        if self.is_nan()
+            || o.is_nan()
+            || self.is_inf() && o.is_zero()
+            || self.is_zero() && o.is_inf()
+        {
+            return LDouble::NAN;
+        }
+        if self.is_inf() || o.is_inf() {
+            return LDouble::inf(sign);
+        }
+        if self.is_zero() || o.is_zero() {
+            return LDouble::zero(sign);
+        }
+        let ((ma, ea), (mb, eb)) = (self.parts(), o.parts());
+        LDouble::from_parts(sign, ma as u128 * mb as u128, ea + eb, false)
+    }
+}
+impl Div for LDouble {
+    type Output = LDouble;
+    fn div(self, o: LDouble) -> LDouble {
+        let sign = self.sign != o.sign;
+        if self.is_nan()
+            || o.is_nan()
+            || self.is_inf() && o.is_inf()
+            || self.is_zero() && o.is_zero()
+        {
+            return LDouble::NAN;
+        }
+        if self.is_inf() || o.is_zero() {
+            return LDouble::inf(sign);
+        }
+        if self.is_zero() || o.is_inf() {
+            return LDouble::zero(sign);
+        }
+        let ((ma, ea), (mb, eb)) = (self.parts(), o.parts());
+        // 64bitずつ2回割って、丸めに足りるだけの商の桁を得る
+        let (ma, mb) = (ma as u128, mb as u128);
+        let (q1, r1) = ((ma << 64) / mb, (ma << 64) % mb);
+        let (q2, r2) = ((r1 << 64) / mb, (r1 << 64) % mb);
+        let m = q1 << 62 | q2 >> 2;
+        let sticky = q2 & 3 != 0 || r2 != 0;
+        LDouble::from_parts(sign, m, ea - eb - 64 - 62, sticky)
+    }
+}
+// +0と-0は等しく、NaNはどの値とも等しくない
+impl PartialEq for LDouble {
+    fn eq(&self, o: &LDouble) -> bool {
+        self.partial_cmp(o) == Some(Ordering::Equal)
+    }
+}
+impl PartialOrd for LDouble {
+    fn partial_cmp(&self, o: &LDouble) -> Option<Ordering> {
+        if self.is_nan() || o.is_nan() {
+            return None;
+        }
+        if self.is_zero() && o.is_zero() {
+            return Some(Ordering::Equal);
+        }
+        if self.sign != o.sign {
+            return Some(if self.sign {
+                Ordering::Less
+            } else {
+                Ordering::Greater
+            });
+        }
+        // 同じ符号なら、指数と仮数を並べたものの大小が絶対値の大小になる
+        let key = |v: &LDouble| (v.exp, v.mant);
+        let ord = key(self).cmp(&key(o));
+        Some(if self.sign { ord.reverse() } else { ord })
+    }
+}
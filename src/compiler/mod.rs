@@ -1,6 +1,9 @@
+#[allow(clippy::module_inception)]
 pub mod compiler;
 mod consts;
+mod eval;
 mod generator;
+mod ldouble;
 mod node;
 mod parser;
 mod type_;
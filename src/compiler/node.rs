@@ -1,4 +1,5 @@
-use super::type_::Type;
+use super::ldouble::LDouble;
+use super::type_::{BitField, Member, Type};
 
 pub type Typed<T> = (T, Type);
 #[derive(Debug, Clone)]
@@ -27,20 +28,48 @@ pub enum Equals {
 pub struct Fcall {
     pub ident: String,
     pub args: Vec<Typed<Expr>>,
+    pub params: Vec<Type>, // 各引数を渡す型。宣言がなければ既定の実引数拡張をした型
+    pub ret_slot: Option<usize>, // 構造体の戻り値を受け取る領域
 }
 #[derive(Debug, Clone)]
 pub struct Fdef {
-    // pub type_: Type,
+    pub ret: Type,
     pub ident: String,
     pub fimpl: Block,
     pub args: Vec<VarDef>,
     pub required_memory: usize,
+    pub linkage: Linkage,        // staticなら.globlで公開しない
+    pub ret_slot: Option<usize>, // 構造体を返すとき、呼び出し元が渡した戻り値の置き場所を退避する位置
+}
+#[derive(Debug, Clone, PartialEq)]
+pub enum Linkage {
+    External,
+    Internal,
+}
+#[derive(Debug, Clone)]
+pub enum GInit {
+    Int(i64),
+    Str(Vec<u8>),                    // 文字列リテラルを指すポインタ
+    Addr(String, i64),               // 関数や大域変数のアドレスにバイト単位のオフセットを足したもの
+    LDouble(LDouble),                // long doubleの値。80bitのビット列のまま置く
+    Bytes(Vec<u8>),                  // char配列に直接置く文字列
+    List(Vec<(usize, Type, GInit)>), // 配列や構造体の要素の位置と型と値。位置の順に並べ、隙間は0で埋める
+}
+#[derive(Debug, Clone)]
+pub struct GVar {
+    pub ident: String, // ラベル。関数内のstatic変数は名前に通し番号を付けて一意にする
+    pub type_: Type,
+    pub init: Option<GInit>,
+    pub linkage: Linkage,
+    pub defined: bool, // externによる宣言のみならfalse。実体は他のオブジェクトにある
 }
 #[derive(Debug, Clone)]
 pub struct Program {
     pub fdefs: Vec<Fdef>,
-    // pub stmt: Vec<Statement>,
-    // pub required_memory: usize,
+    pub gvars: Vec<GVar>,
+    pub asms: Vec<String>, // ファイルスコープのasm。関数より前にそのまま出力する
+                           // pub stmt: Vec<Statement>,
+                           // pub required_memory: usize,
 }
 
 #[derive(Debug, Clone)]
@@ -70,18 +99,97 @@ pub struct Block {
     pub stmts: Vec<Statement>,
 }
 #[derive(Debug, Clone)]
+pub struct Label {
+    pub ident: String,
+    pub stmt: Box<Statement>,
+}
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
 pub enum Statement {
     If(If),
     For(For),
     While(While),
     Stmt(Stmt),
     MStmt(Block),
+    Label(Label),
+    Goto(String),
+    Asm(Asm),
     Nothing,
 }
+// asm volatile("テンプレート" : 出力 : 入力 : 破壊するレジスタ)。テンプレートの%Nは出力、入力の順に数えたN番目の被演算子になる
 #[derive(Debug, Clone)]
+pub struct Asm {
+    pub template: String,
+    pub outputs: Vec<AsmOperand>,
+    pub inputs: Vec<AsmOperand>,
+    pub clobbers: Vec<String>,
+}
+impl Asm {
+    // 被演算子も破壊するレジスタも無ければ、テンプレートをそのまま出力する
+    pub fn is_basic(&self) -> bool {
+        self.outputs.is_empty() && self.inputs.is_empty() && self.clobbers.is_empty()
+    }
+    // テンプレートの%Nをoperands[N]に、%%を%に置き換える。範囲外の番号があればNone
+    pub fn expand(&self, operands: &[String]) -> Option<String> {
+        if self.is_basic() {
+            return Some(self.template.clone());
+        }
+        let mut text = String::new();
+        let mut chars = self.template.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                text.push(c);
+                continue;
+            }
+            if chars.next_if_eq(&'%').is_some() {
+                text.push('%');
+                continue;
+            }
+            let mut n = None;
+            while let Some(d) = chars.peek().and_then(|d| d.to_digit(10)) {
+                n = Some(n.unwrap_or(0) * 10 + d as usize);
+                chars.next();
+            }
+            match n {
+                Some(n) => text.push_str(operands.get(n)?),
+                None => text.push('%'),
+            }
+        }
+        Some(text)
+    }
+}
+#[derive(Debug, Clone)]
+pub struct AsmOperand {
+    pub constraint: char, // rは任意のレジスタ、aはrax、Dはrdi、mはメモリ、iは定数
+    pub read: bool,       // 入力か、+の付いた出力なら値を読み込んでおく
+    pub expr: Typed<Equality>,
+}
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
 pub enum Expr {
     Asgn(ExprAssign),
-    VarAsgn(Vec<VarDef>, Option<Assign>),
+    VarAsgn(Vec<VarInit>),
+}
+// 宣言子ごとの変数と初期化子
+pub type VarInit = (VarDef, Option<Initializer>);
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
+pub enum Initializer {
+    Assign(Assign),
+    List(Vec<InitElem>), // 波括弧や文字列の初期化子。書かれていない部分は0で埋める
+}
+// 配列や構造体の初期化子を、スカラの要素ごとに展開したもの
+#[derive(Debug, Clone)]
+pub struct InitElem {
+    pub offset: usize, // 変数の先頭からの位置
+    pub type_: Type,
+    pub value: InitValue,
+}
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
+pub enum InitValue {
+    Expr(Typed<Assign>),
+    Str(Vec<u8>), // char配列に置く文字列。配列の長さに合わせて\0で埋めてある
 }
 impl Expr {
     pub fn does_return(&self) -> bool {
@@ -109,7 +217,6 @@ pub struct Asgn {
 pub struct VarDef {
     pub ident: String,
     pub type_: Type,
-    pub _ref_count_: usize,
     pub offset: usize,
     pub _arrs: Vec<Typed<Expr>>,
 }
@@ -120,6 +227,20 @@ pub enum Assign {
     Asgn(Asgn),
 }
 impl Assign {
+    // 演算子を伴わない単一のprimaryならそれを返す
+    pub fn primary(&self) -> Option<&Primary> {
+        match self {
+            Assign::Rv(r) => r.eq.0.primary(),
+            Assign::Asgn(_) => None,
+        }
+    }
+    // 演算子を伴わない単一のunaryならそれを返す
+    pub fn unary(&self) -> Option<&Unary> {
+        match self {
+            Assign::Rv(r) => r.eq.0.unary(),
+            Assign::Asgn(_) => None,
+        }
+    }
     pub fn type_(&self) -> Type {
         match &self {
             Assign::Rv(r) => r.eq.1.clone(),
@@ -134,7 +255,22 @@ pub struct Equality {
 }
 impl Equality {
     pub fn is_lvar(&self) -> bool {
-        self.first.0.is_lvar() && self.relationals.len() == 0
+        self.first.0.is_lvar() && self.relationals.is_empty()
+    }
+    // 演算子を伴わない単一のprimaryならそれを返す
+    pub fn primary(&self) -> Option<&Primary> {
+        self.unary()?.primary()
+    }
+    // 二項演算子を伴わない単一のunaryならそれを返す
+    pub fn unary(&self) -> Option<&Unary> {
+        if !self.relationals.is_empty() || !self.first.0.adds.is_empty() {
+            return None;
+        }
+        let add = &self.first.0.first.0;
+        if !add.muls.is_empty() || !add.first.0.unarys.is_empty() {
+            return None;
+        }
+        Some(&add.first.0.first.0)
     }
 }
 #[derive(Debug, Clone)]
@@ -145,7 +281,7 @@ pub struct Relational {
 }
 impl Relational {
     pub fn is_lvar(&self) -> bool {
-        if self.ope.is_some() || self.adds.len() > 0 {
+        if self.ope.is_some() || !self.adds.is_empty() {
             return false;
         }
         self.first.0.is_lvar()
@@ -159,7 +295,7 @@ pub struct Add {
 }
 impl Add {
     pub fn is_lvar(&self) -> bool {
-        if self.ope.is_some() || self.muls.len() > 0 {
+        if self.ope.is_some() || !self.muls.is_empty() {
             return false;
         }
         self.first.0.is_lvar()
@@ -173,7 +309,7 @@ pub struct Mul {
 }
 impl Mul {
     pub fn is_lvar(&self) -> bool {
-        if self.ope.is_some() || self.unarys.len() > 0 {
+        if self.ope.is_some() || !self.unarys.is_empty() {
             return false;
         }
         self.first.0.is_lvar()
@@ -194,45 +330,188 @@ pub struct UnaryVar {
     pub ope: Option<MulDiv>,
     pub prim: (Primary, Type),
     pub _arrs: Vec<Typed<Expr>>,
+    pub line: usize, // 添字のある行。領域外アクセスの報告に使う
+}
+// s.mやp->mのメンバアクセス。メンバが配列ならその後ろの添字もここに持つ
+#[derive(Debug, Clone)]
+pub struct UnaryMember {
+    pub ope: Option<MulDiv>,
+    pub sign: Option<AddSub>, // 単項の+-。メンバアクセスを含めた全体にかかる
+    pub unary: Box<(Unary, Type)>,
+    pub arrow: bool,
+    pub member: Member,
+    pub _arrs: Vec<Typed<Expr>>,
+    pub line: usize,
+}
+// fp(x)や(*fp)(x)のように、関数名でなく式の値を呼び出す
+#[derive(Debug, Clone)]
+pub struct UnaryCall {
+    pub ope: Option<MulDiv>,
+    pub sign: Option<AddSub>,
+    pub callee: Box<(Unary, Type)>,
+    pub args: Vec<Typed<Expr>>,
+    pub params: Vec<Type>,
+    pub ret_slot: Option<usize>,
+}
+// (type)xのキャスト。整数なら変換先の型に合わせて切り詰めや拡張をする
+#[derive(Debug, Clone)]
+pub struct UnaryCast {
+    pub ope: Option<MulDiv>,
+    pub sign: Option<AddSub>,
+    pub to: Type,
+    pub unary: Box<(Unary, Type)>,
+}
+// 実行時に大きさが決まる可変長配列へのsizeof。定数になるsizeofは数値リテラルに置き換える
+#[derive(Debug, Clone)]
+pub struct UnarySizeof {
+    pub ope: Option<MulDiv>,
+    pub unary: Box<(Unary, Type)>,
 }
 #[derive(Debug, Clone)]
 pub enum Unary {
     Ptr(UnaryPtr),
     Var(UnaryVar),
+    Sizeof(UnarySizeof),
+    Member(UnaryMember),
+    Call(UnaryCall),
+    Cast(UnaryCast),
 }
 impl Unary {
     pub fn is_lvar(&self) -> bool {
         match self {
+            // 添字アクセスの結果は、対象が何であれ左辺値になる
+            Unary::Var(p) if !p._arrs.is_empty() => p.prim.0.ope.is_none(),
             Unary::Var(p) => p.prim.0.is_lvar(),
             Unary::Ptr(p) => match p.ope {
                 PtrOpe::Deref => false,
-                PtrOpe::Ref => p.unary.0.is_lvar(),
+                PtrOpe::Ref => true, // *の結果は常に左辺値
             },
+            Unary::Sizeof(_) => false,
+            Unary::Member(m) => m.sign.is_none(),
+            Unary::Call(_) | Unary::Cast(_) => false,
         }
     }
     pub fn ope(&self) -> &Option<MulDiv> {
         match self {
             Unary::Ptr(p) => p.unary.0.ope(),
             Unary::Var(p) => &p.ope,
+            Unary::Sizeof(s) => &s.ope,
+            Unary::Member(m) => &m.ope,
+            Unary::Call(c) => &c.ope,
+            Unary::Cast(c) => &c.ope,
+        }
+    }
+    // 後置演算子で包む際に、前に付いていた演算子を外側へ移すために取り出す
+    pub fn take_prefix(&mut self) -> (Option<MulDiv>, Option<AddSub>) {
+        match self {
+            Unary::Var(v) => (v.ope.take(), v.prim.0.ope.take()),
+            Unary::Member(m) => (m.ope.take(), m.sign.take()),
+            Unary::Call(c) => (c.ope.take(), c.sign.take()),
+            Unary::Cast(c) => (c.ope.take(), c.sign.take()),
+            _ => (None, None),
+        }
+    }
+    #[allow(dead_code)]
+    // 左辺値が指すオブジェクトの型。式の型と違い、宣言やポインタの指す先の修飾子を持つ
+    pub fn object_type(&self) -> Option<Type> {
+        let (mut t, arrs) = match self {
+            Unary::Var(v) => match &v.prim.0.node.0 {
+                PrimaryNode::Lv(l) => (l.ident()._type_.clone(), &v._arrs),
+                PrimaryNode::Compound(c) => (c.def.type_.clone(), &v._arrs),
+                _ => (v.prim.1.clone(), &v._arrs),
+            },
+            Unary::Ptr(p) => match p.ope {
+                PtrOpe::Ref => return p.unary.1.deref_qualified(),
+                PtrOpe::Deref => return None,
+            },
+            // メンバは構造体の修飾子も受け継ぐ
+            Unary::Member(m) => {
+                let container = if m.arrow {
+                    m.unary.1.deref_qualified()?
+                } else {
+                    m.unary.0.object_type().unwrap_or(m.unary.1.clone())
+                };
+                (m.member.type_.qualify(container.qualifiers()), &m._arrs)
+            }
+            _ => return None,
+        };
+        for _ in arrs.iter() {
+            t = t.deref_qualified()?;
+        }
+        Some(t)
+    }
+    // ビットフィールドのメンバならその宣言した型と位置を返す
+    pub fn bit_field(&self) -> Option<(&Type, BitField)> {
+        match self {
+            Unary::Member(m) if m.sign.is_none() => match &m.member.type_ {
+                Type::Bits(b) => Some((&b.0, b.1)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+    // (s.x)のように括弧で囲んだビットフィールドのメンバも含めて、ビットフィールドかどうか
+    pub fn is_bit_field(&self) -> bool {
+        if self.bit_field().is_some() {
+            return true;
+        }
+        match self.primary() {
+            Some(Primary {
+                ope: None,
+                node: (PrimaryNode::Expr(e), _),
+            }) => match e.as_ref() {
+                Expr::Asgn(ea) => !ea.ret && ea.assign.unary().is_some_and(|u| u.is_bit_field()),
+                Expr::VarAsgn(_) => false,
+            },
+            _ => false,
+        }
+    }
+    // 添字を伴わない単一のprimaryならそれを返す
+    pub fn primary(&self) -> Option<&Primary> {
+        match self {
+            Unary::Var(v) if v._arrs.is_empty() => Some(&v.prim.0),
+            _ => None,
         }
     }
     pub fn ident(&self) -> Option<&String> {
         match self {
             Unary::Var(p) => p.prim.0.ident(),
             Unary::Ptr(p) => p.unary.0.ident(),
+            Unary::Sizeof(_) => None,
+            Unary::Member(m) => Some(&m.member.name),
+            Unary::Call(_) => None,
+            Unary::Cast(c) => c.unary.0.ident(),
         }
     }
 }
 #[derive(Debug, Clone)]
 pub enum Lvar {
-    Id(Ident),
+    Id(Ident),     // rbp相対
+    Global(Ident), // rip相対。nameがそのままラベルになる
+}
+impl Lvar {
+    pub fn ident(&self) -> &Ident {
+        match self {
+            Lvar::Id(i) | Lvar::Global(i) => i,
+        }
+    }
 }
 #[derive(Debug, Clone)]
 pub enum PrimaryNode {
-    Num((String, Type)),
+    Num((i64, Type)),
+    Float(f64),      // floatなら値はfloatに丸めてある
+    LDouble(String), // long doubleのリテラル。doubleでは精度が足りないので字句のままアセンブラに渡す
+    Str(Vec<u8>),    // 終端の\0は含まない
     Lv(Lvar),
     Expr(Box<Expr>),
     Fcall(Fcall),
+    Compound(Compound),
+}
+// 複合リテラル。名前の無いローカル変数を、評価するたびに初期化子で初期化する
+#[derive(Debug, Clone)]
+pub struct Compound {
+    pub def: VarDef,
+    pub elems: Vec<InitElem>,
 }
 #[derive(Debug, Clone)]
 pub struct Primary {
@@ -244,14 +523,11 @@ impl Primary {
         if self.ope.is_some() {
             return false;
         }
-        match &self.node.0 {
-            PrimaryNode::Lv(_) => true,
-            _ => false,
-        }
+        matches!(&self.node.0, PrimaryNode::Lv(_) | PrimaryNode::Compound(_))
     }
     pub fn ident(&self) -> Option<&String> {
         match &self.node.0 {
-            PrimaryNode::Lv(Lvar::Id(i)) => Some(&i.name),
+            PrimaryNode::Lv(l) => Some(&l.ident().name),
             _ => None,
         }
     }
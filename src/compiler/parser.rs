@@ -1,16 +1,23 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
 
 use super::{
     consts::{
-        BLOCK_EXPECTED, BRACE_NOT_BALANCED, FOR, IDENTITY_OFFSET, IDENTITY_WANTED, IF, INT,
-        NOT_AVAILABLE_FOR_ARRAY_INDEX, RETURN, TYPE_WANTED, TYPES, WHILE,
+        ALIGNOF, ASM, ASM_VOLATILE, BLOCK_EXPECTED, BRACE_NOT_BALANCED, CHAR, CONST, DOUBLE, ENUM,
+        EXTERN, FLOAT, FOR, GOTO, IDENTITY_OFFSET, IDENTITY_WANTED, IF, INT, LONG,
+        NOT_AVAILABLE_FOR_ARRAY_INDEX, RETURN, SHORT, SIGNED, SIZEOF, STATIC, STATIC_ASSERT,
+        STRUCT, TYPE_WANTED, TYPEDEF, TYPES, UNION, UNSIGNED, VOID, VOLATILE, WHILE,
     },
     node::{
-        Add, AddSub, Asgn, Assign, Block, Compare, Equality, Equals, Expr, ExprAssign, Fcall, Fdef,
-        For, Ident, If, Lvar, Mul, MulDiv, Primary, PrimaryNode, Program, PtrOpe, Relational, Rvar,
-        Statement, Stmt, Typed, Unary, UnaryPtr, UnaryVar, VarDef, While,
+        Add, AddSub, Asgn, Asm, AsmOperand, Assign, Block, Compare, Compound, Equality, Equals,
+        Expr, ExprAssign, Fcall, Fdef, For, GInit, GVar, Ident, If, InitElem, InitValue,
+        Initializer, Label, Linkage, Lvar, Mul, MulDiv, Primary, PrimaryNode, Program, PtrOpe,
+        Relational, Rvar, Statement, Stmt, Typed, Unary, UnaryCall, UnaryCast, UnaryMember,
+        UnaryPtr, UnarySizeof, UnaryVar, VarDef, VarInit, While,
     },
-    type_::Type,
+    type_::{BitField, Member, Qualifiers, StructBody, StructDef, Type, align_to},
 };
 const DEBUG: bool = false;
 #[derive(Debug)]
@@ -29,51 +36,188 @@ pub struct Parser<'a> {
     pub required_memory: usize,
     pub funcs: HashMap<String, Type>,
     pub idents: HashMap<String, VarDef>,
+    pub globals: HashMap<String, GVar>,
+    pub tags: HashMap<String, Type>,  // 構造体・共用体・列挙型のタグ
+    pub tag_scope: HashSet<String>,   // 今のブロックで宣言したタグ。外側のタグは定義し直すと隠れる
+    pub enum_bodies: HashSet<String>, // 今のブロックで列挙定数の並びまで定義した列挙型のタグ
+    pub enums: HashMap<String, i64>,  // 列挙定数。変数と同じくブロックごとに持つ
+    pub typedefs: HashMap<String, Type>, // typedefで付けた型の別名
+    pub scope: HashSet<String>, // 今のブロックで宣言した変数・typedef名・列挙定数。外側の同じ名前は宣言し直すと隠れる
     pub read_lines: usize,
     pub line_index: usize,
+    // ラベルは関数単位で解決する。gotoは後方のラベルも指せるので関数を読み終えてから未定義チェックする
+    pub labels: HashSet<String>,
+    pub gotos: Vec<(String, ParseFailure)>,
+    pub func_linkages: HashMap<String, Linkage>,
+    pub statics: Vec<GVar>, // 関数内のstatic変数。プログラム全体で集めて大域変数と一緒に置く
+}
+type Checkpoint = (usize, usize, usize);
+// 宣言子を読んだ結果
+struct Declarator {
+    ident: Option<String>,
+    type_: Type,
+    vla: Vec<Typed<Expr>>,       // 可変長配列なら各次元の大きさの式
+    params: Option<Vec<VarDef>>, // 識別子に直接引数リストが付いていれば関数の宣言
 }
 trait IsToken {
-    fn is_token_parts(self) -> bool;
-    fn is_token_first(self) -> bool;
+    fn is_token_parts(&self) -> bool;
+    fn is_token_first(&self) -> bool;
 }
 impl IsToken for char {
-    fn is_token_first(self) -> bool {
-        self.is_ascii_alphabetic() || self == '_'
+    fn is_token_first(&self) -> bool {
+        self.is_ascii_alphabetic() || *self == '_'
+    }
+    fn is_token_parts(&self) -> bool {
+        self.is_ascii_alphanumeric() || *self == '_'
+    }
+}
+// 構造体のメンバの名前と型。ビットフィールドなら幅も持つ。名前の無いビットフィールドは配置を調整するだけ
+type MemberDecl = (Option<String>, Type, Option<usize>);
+// 定数になったsizeofなどはsize_tの数値リテラルとして扱う
+fn num_unary(ope: Option<MulDiv>, n: i64, line: usize) -> Typed<Unary> {
+    let node = (PrimaryNode::Num((n, Type::ULong)), Type::ULong);
+    (
+        Unary::Var(UnaryVar {
+            ope,
+            prim: (Primary { ope: None, node }, Type::ULong),
+            _arrs: vec![],
+            line,
+        }),
+        Type::ULong,
+    )
+}
+// 配列や構造体のindex番目の要素の型と位置。共用体は先頭のメンバしか順番には初期化できない
+fn sub_object(t: &Type, index: usize) -> Option<(Type, usize)> {
+    match t {
+        // 長さを省略した配列は、初期化子の要素数だけ長さを持つ
+        Type::Array(a) if a.1 == 0 || index < a.1 => Some((a.0.clone(), index * a.0.sizeof())),
+        Type::Struct(s) if !s.is_union || index == 0 => {
+            let body = s.body.borrow();
+            let m = body.as_ref()?.members.get(index)?;
+            Some((m.type_.clone(), m.offset))
+        }
+        _ => None,
+    }
+}
+// ビットフィールドを値として読んだときの型。intで表せる幅ならintに拡張される
+fn bit_field_type(t: &Type, width: usize) -> Type {
+    match width {
+        ..32 => Type::Int,
+        32 if t.is_signed() || t.sizeof() > 4 => t.unqualified(),
+        32 => Type::UInt,
+        _ => t.unqualified(),
+    }
+}
+// 長さを省略した配列の長さを初期化子の要素数で決める
+fn complete_array(t: &Type, len: usize) -> Type {
+    match t {
+        Type::Array(a) if a.1 == 0 => Type::Array(Box::new((a.0.clone(), len))),
+        t => t.clone(),
+    }
+}
+// 同じ位置を初期化し直したら、後に書いたものが優先される
+fn push_init(elems: &mut Vec<InitElem>, e: InitElem) {
+    let (start, end) = bit_range(&e);
+    elems.retain(|x| {
+        let (x_start, x_end) = bit_range(x);
+        x_end <= start || end <= x_start
+    });
+    elems.push(e);
+}
+// 要素が書き込む範囲をビット単位で表す。ビットフィールドは同じ単位の他のビットと重ならない
+fn bit_range(e: &InitElem) -> (usize, usize) {
+    match &e.type_ {
+        Type::Bits(b) => {
+            let start = e.offset * 8 + b.1.shift;
+            (start, start + b.1.width)
+        }
+        t => (e.offset * 8, (e.offset + t.sizeof()) * 8),
     }
-    fn is_token_parts(self) -> bool {
-        self.is_ascii_alphanumeric() || self == '_'
+}
+// 中身のわからない構造体は大きさが決まらないので変数やメンバにできない
+fn is_incomplete(t: &Type) -> bool {
+    match t.base() {
+        Type::Struct(s) => !s.is_complete(),
+        Type::Array(a) => is_incomplete(&a.0),
+        _ => false,
     }
 }
 fn type_match(_a: &Type, _b: &Type) -> bool {
     // 本来は演算の種類ごとに計算可能性を考える必要があるのでこれ一つの関数で処理することはできない
     // だけど一旦はこれでよし
-    return true;
+    true
     // if a == b {
     //     return true;
     // }
     // match a {
     //     Type::_Panic => false,
     //     Type::Int => match b {
-    //         Type::Int | Type::LInt => true,
+    //         Type::Int => true,
     //         _ => false,
     //     },
-    //     Type::LInt => type_match(&Type::Int, b),
     //     Type::Ptr(aa) => match b {
     //         Type::Ptr(bb) => type_match(aa, bb),
     //         _ => false,
     //     },
     // }
 }
+// 整数リテラルの接尾辞から型の候補を優先順に返す。10進数は接尾辞にuがなければ符号なしにならない
+fn literal_types(suffix: &str, decimal: bool) -> Option<Vec<Type>> {
+    let lower = suffix.to_ascii_lowercase();
+    let (unsigned, long) = match lower.as_str() {
+        "" => (false, ""),
+        "u" => (true, ""),
+        "l" | "ll" => (false, suffix),
+        "ul" | "ull" => (true, &suffix[1..]),
+        "lu" | "llu" => (true, &suffix[..suffix.len() - 1]),
+        _ => return None,
+    };
+    // lLのように大文字小文字が混ざったものは不正
+    if long == "lL" || long == "Ll" {
+        return None;
+    }
+    let types = match (long.len(), unsigned, decimal) {
+        (0, false, true) => vec![Type::Int, Type::Long, Type::LongLong],
+        (0, false, false) => vec![
+            Type::Int,
+            Type::UInt,
+            Type::Long,
+            Type::ULong,
+            Type::LongLong,
+            Type::ULongLong,
+        ],
+        (0, true, _) => vec![Type::UInt, Type::ULong, Type::ULongLong],
+        (1, false, true) => vec![Type::Long, Type::LongLong],
+        (1, false, false) => vec![Type::Long, Type::ULong, Type::LongLong, Type::ULongLong],
+        (1, true, _) => vec![Type::ULong, Type::ULongLong],
+        (_, false, true) => vec![Type::LongLong],
+        (_, false, false) => vec![Type::LongLong, Type::ULongLong],
+        (_, true, _) => vec![Type::ULongLong],
+    };
+    Some(types)
+}
 impl Parser<'_> {
     fn dbg(&self, note: String) {
         if DEBUG {
-            if note.len() > 0 {
+            if !note.is_empty() {
                 print!("{}/", note);
             }
-            println!("{:?}, {}", self.input.chars().nth(self.index), self.index);
+            println!("{:?}, {}", self.rest().chars().next(), self.index);
             println!("\t{}\n\t{}^", self.input, " ".repeat(self.index));
         }
     }
+    // 位置はバイト単位で持つ。まだ読んでいない部分
+    fn rest(&self) -> &str {
+        &self.input[self.index..]
+    }
+    fn checkpoint(&self) -> Checkpoint {
+        (self.index, self.read_lines, self.line_index)
+    }
+    fn rollback(&mut self, (index, read_lines, line_index): Checkpoint) {
+        self.index = index;
+        self.read_lines = read_lines;
+        self.line_index = line_index;
+    }
     fn succ(&mut self, count: usize) {
         self.index += count;
         self.line_index += count;
@@ -89,22 +233,6 @@ impl Parser<'_> {
             None
         }
     }
-    fn consume_f(&mut self, checker: fn(check: char) -> bool) -> Option<String> {
-        self.space();
-        let mut str = "".to_string();
-        loop {
-            if self.empty() {
-                break;
-            }
-            match self.top_f(checker) {
-                None => break,
-                Some(c) => {
-                    str.push(c);
-                }
-            };
-        }
-        if str.is_empty() { None } else { Some(str) }
-    }
     fn consume_expect(&mut self, checker: fn(check: char) -> bool, expect: &str) -> Option<String> {
         self.space();
         let mut str = "".to_string();
@@ -113,11 +241,11 @@ impl Parser<'_> {
             if self.empty() {
                 break;
             }
-            let c = self.input.chars().nth(self.index + offset);
+            let c = self.rest()[offset..].chars().next();
             if c.is_none() || !checker(c.unwrap()) {
                 break;
             }
-            offset += 1;
+            offset += c.unwrap().len_utf8();
             str.push(c.unwrap())
         }
         if !str.eq(expect) {
@@ -127,45 +255,69 @@ impl Parser<'_> {
         Some(str)
     }
 
+    // 大文字小文字を区別せずに接頭辞を読む
+    fn consume_expect_ci(&mut self, expect: &str) -> bool {
+        let matched = self
+            .rest()
+            .chars()
+            .take(expect.len())
+            .map(|c| c.to_ascii_lowercase())
+            .eq(expect.chars());
+        if matched {
+            self.succ(expect.len());
+        }
+        matched
+    }
     fn empty(&mut self) -> bool {
         self.space();
         self.index == self.input.len()
     }
     fn fail(&self, reason: String) -> ParseFailure {
-        let line = match self.input.split('\n').nth(self.read_lines) {
-            Some(str) => Some(str.into()),
-            _ => None,
-        };
+        let line = self
+            .input
+            .split('\n')
+            .nth(self.read_lines)
+            .map(|str| str.into());
         self.dbg(format!("fail {}", reason));
         ParseFailure {
             index: self.line_index,
             read_line: self.read_lines,
             source: line,
-            reason: reason,
+            reason,
         }
     }
+    // 翻訳は続けられるが、誤りの可能性が高いもの
+    fn warn(&self, reason: String) {
+        let line = self
+            .input
+            .split('\n')
+            .nth(self.read_lines)
+            .unwrap_or_default();
+        eprintln!(
+            "warning: {}\nat line {}\n    {}",
+            reason,
+            self.read_lines + 1,
+            line
+        );
+    }
     fn check_top(&mut self, var: &str) -> bool {
         self.space();
-        self.input
-            .chars()
-            .skip(self.index)
-            .take(var.len())
-            .eq(var.chars())
+        self.rest().chars().take(var.len()).eq(var.chars())
     }
     fn check_top_f(&mut self, checker: fn(check: char) -> bool) -> bool {
         self.space();
-        match self.input.chars().nth(self.index) {
+        match self.rest().chars().next() {
             None => false,
             Some(c) => checker(c),
         }
     }
     fn top_f(&mut self, checker: fn(check: char) -> bool) -> Option<char> {
         self.space();
-        match self.input.chars().nth(self.index) {
+        match self.rest().chars().next() {
             None => None,
             Some(c) => {
                 if checker(c) {
-                    self.succ(1);
+                    self.succ(c.len_utf8());
                     Some(c)
                 } else {
                     None
@@ -176,19 +328,13 @@ impl Parser<'_> {
 
     fn space(&mut self) {
         loop {
-            if self
-                .input
-                .chars()
-                .skip(self.index)
-                .take(2)
-                .eq("\r\n".chars())
-            {
+            if self.rest().chars().take(2).eq("\r\n".chars()) {
                 self.read_lines += 1;
                 self.succ(2);
                 self.line_index = 0;
                 continue;
             }
-            match self.input.chars().nth(self.index) {
+            match self.rest().chars().next() {
                 Some(' ') => {
                     self.succ(1);
                 }
@@ -204,48 +350,262 @@ impl Parser<'_> {
     fn p_exp(&mut self, ope: Option<AddSub>) -> ParseResult<Typed<Primary>> {
         let node = self.parenthesized(|p| p.expr())?;
         let type_ = node.1.clone();
-        return Ok((
+        Ok((
             Primary {
                 ope,
                 node: (PrimaryNode::Expr(Box::new(node.0)), type_.clone()),
             },
             type_,
-        ));
+        ))
+    }
+    // 空白を読み飛ばさずに条件を満たす文字を読めるだけ読む
+    fn consume_raw(&mut self, checker: impl Fn(char) -> bool) -> String {
+        let mut str = String::new();
+        while let Some(c) = self.rest().chars().next() {
+            if !checker(c) {
+                break;
+            }
+            str.push(c);
+            self.succ(c.len_utf8());
+        }
+        str
     }
     fn p_num(&mut self, ope: Option<AddSub>) -> ParseResult<Typed<Primary>> {
         self.space();
-        let mut raw_num: String = "".into();
+        // 小数点か指数部が続くなら浮動小数点数
+        let checkpoint = self.checkpoint();
+        if !self.consume_expect_ci("0x") {
+            self.consume_raw(|c| c.is_ascii_digit());
+            if matches!(self.rest().chars().next(), Some('.' | 'e' | 'E')) {
+                self.rollback(checkpoint);
+                return self.p_float(ope);
+            }
+        }
+        self.rollback(checkpoint);
+        let radix = if self.consume_expect_ci("0x") {
+            16
+        } else if self.consume_expect_ci("0b") {
+            2
+        } else if self.check_top("0") {
+            8 // 0始まりは8進数。"0"単体も8進数の0として扱う
+        } else {
+            10
+        };
+        let digits = self.consume_raw(|c| c.is_digit(radix) || (radix != 16 && c.is_ascii_digit()));
+        if digits.is_empty() {
+            return Err(self.fail(format!("no digits in base {} literal", radix)));
+        }
+        if let Some(c) = digits.chars().find(|c| !c.is_digit(radix)) {
+            return Err(self.fail(format!("invalid digit {} in base {} literal", c, radix)));
+        }
+        let suffix = self.consume_raw(|c| c.is_token_parts());
+        let value = u64::from_str_radix(&digits, radix);
+        if value.is_err() {
+            return Err(self.fail(format!(
+                "integer literal {} is too large to be represented in any integer type",
+                digits
+            )));
+        }
+        let value = value.unwrap();
+        let candidates = literal_types(&suffix, radix == 10);
+        if candidates.is_none() {
+            return Err(self.fail(format!("invalid suffix {} on integer literal", suffix)));
+        }
+        // 候補の型のうち、値を表現できる最初の型がリテラルの型になる
+        let type_ = candidates
+            .unwrap()
+            .into_iter()
+            .find(|t| value <= t.max_value());
+        if type_.is_none() {
+            return Err(self.fail(format!(
+                "integer literal {} is too large for its type",
+                digits
+            )));
+        }
+        let type_ = type_.unwrap();
+        Ok((
+            Primary {
+                ope,
+                node: (
+                    PrimaryNode::Num((value as i64, type_.clone())),
+                    type_.clone(),
+                ),
+            },
+            type_,
+        ))
+    }
+    fn p_float(&mut self, ope: Option<AddSub>) -> ParseResult<Typed<Primary>> {
+        let mut text = self.consume_raw(|c| c.is_ascii_digit());
+        if self.rest().starts_with('.') {
+            self.succ(1);
+            text.push('.');
+            text.push_str(&self.consume_raw(|c| c.is_ascii_digit()));
+        }
+        if matches!(self.rest().chars().next(), Some('e' | 'E')) {
+            self.succ(1);
+            text.push('e');
+            if let Some(sign @ ('+' | '-')) = self.rest().chars().next() {
+                self.succ(1);
+                text.push(sign);
+            }
+            let exp = self.consume_raw(|c| c.is_ascii_digit());
+            if exp.is_empty() {
+                return Err(self.fail("exponent has no digits".into()));
+            }
+            text.push_str(&exp);
+        }
+        let suffix = self.consume_raw(|c| c.is_token_parts());
+        let Ok(value) = text.parse::<f64>() else {
+            return Err(self.fail(format!("invalid floating literal {}", text)));
+        };
+        let (node, type_) = match suffix.as_str() {
+            "" => (PrimaryNode::Float(value), Type::Double),
+            "f" | "F" => (PrimaryNode::Float(value as f32 as f64), Type::Float),
+            "l" | "L" => (PrimaryNode::LDouble(text), Type::LongDouble),
+            _ => return Err(self.fail(format!("invalid suffix {} on floating literal", suffix))),
+        };
+        Ok((
+            Primary {
+                ope,
+                node: (node, type_.clone()),
+            },
+            type_,
+        ))
+    }
+    // 文字・文字列リテラルの中身は空白を読み飛ばさずに1文字ずつ読む
+    fn literal_char(&mut self, quote: char) -> ParseResult<Vec<u8>> {
+        let c = self.rest().chars().next();
+        if c.is_none() || c == Some('\n') {
+            return Err(self.fail(format!("missing terminating {} character", quote)));
+        }
+        let c = c.unwrap();
+        self.succ(c.len_utf8());
+        if c != '\\' {
+            let mut buf = [0; 4];
+            return Ok(c.encode_utf8(&mut buf).as_bytes().to_vec());
+        }
+        let e = self.rest().chars().next();
+        if e.is_none() {
+            return Err(self.fail("escape sequence expected".into()));
+        }
+        self.succ(e.unwrap().len_utf8());
+        let byte = match e.unwrap() {
+            'n' => b'\n',
+            't' => b'\t',
+            'r' => b'\r',
+            'a' => 0x07,
+            'b' => 0x08,
+            'f' => 0x0C,
+            'v' => 0x0B,
+            'e' => 0x1B,
+            'x' => {
+                let mut value: u32 = 0;
+                let mut digits = 0;
+                while let Some(d) = self.rest().chars().next().and_then(|d| d.to_digit(16)) {
+                    value = value * 16 + d;
+                    digits += 1;
+                    self.succ(1);
+                }
+                if digits == 0 {
+                    return Err(self.fail("\\x used with no following hex digits".into()));
+                }
+                if value > 0xFF {
+                    return Err(self.fail("hex escape sequence out of range".into()));
+                }
+                value as u8
+            }
+            o @ '0'..='7' => {
+                let mut value = o.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    match self.rest().chars().next().and_then(|d| d.to_digit(8)) {
+                        Some(d) => value = value * 8 + d,
+                        None => break,
+                    }
+                    self.succ(1);
+                }
+                if value > 0xFF {
+                    return Err(self.fail("octal escape sequence out of range".into()));
+                }
+                value as u8
+            }
+            // \\, \', \", \? はそのままの文字になる
+            c @ ('\\' | '\'' | '"' | '?') => c as u8,
+            c => return Err(self.fail(format!("unknown escape sequence \\{}", c))),
+        };
+        Ok(vec![byte])
+    }
+    fn p_char(&mut self, ope: Option<AddSub>) -> ParseResult<Typed<Primary>> {
+        self.consume("'");
+        let mut bytes = Vec::new();
         loop {
-            if self.empty() {
+            if self.rest().starts_with('\'') {
+                self.succ(1);
                 break;
             }
-            match self.top_f(|c| c.is_numeric()) {
-                None => break,
-                Some(c) => raw_num.push(c),
-            }
+            bytes.extend(self.literal_char('\'')?);
         }
-        if raw_num.len() == 0 {
-            return Err(self.fail("number expected".into()));
+        let value = match bytes.len() {
+            0 => return Err(self.fail("empty character constant".into())),
+            // 文字定数の型はint。charは符号付きなので128以上は負になる
+            1 => bytes[0] as i8 as i32,
+            // 複数の文字はgccと同じく先頭から順に上位のバイトへ詰める。intに収まらない分は捨てる
+            n => {
+                self.warn(if n > 4 {
+                    "character constant too long for its type".into()
+                } else {
+                    "multi-character character constant".into()
+                });
+                bytes.iter().fold(0u32, |v, b| v << 8 | *b as u32) as i32
+            }
+        };
+        Ok((
+            Primary {
+                ope,
+                node: (PrimaryNode::Num((value as i64, Type::Int)), Type::Int),
+            },
+            Type::Int,
+        ))
+    }
+    fn p_str(&mut self, ope: Option<AddSub>) -> ParseResult<Typed<Primary>> {
+        let mut bytes = Vec::new();
+        // 隣接する文字列リテラルは1つに連結する
+        while self.consume("\"").is_some() {
+            loop {
+                if self.rest().starts_with('"') {
+                    self.succ(1);
+                    break;
+                }
+                bytes.extend(self.literal_char('"')?);
+            }
         }
+        // 終端の\0を含めたchar[N]。式の中では先頭要素へのポインタに変換される
+        let type_ = Type::Array(Box::new((Type::Char, bytes.len() + 1)));
         Ok((
             Primary {
-                ope: ope,
-                node: (PrimaryNode::Num((raw_num, Type::LInt)), Type::LInt),
+                ope,
+                node: (PrimaryNode::Str(bytes), type_.clone()),
             },
-            Type::LInt,
+            type_,
         ))
     }
     fn get_ident(&mut self) -> Option<String> {
         let first = self.top_f(|c| c.is_token_first())?;
-        let tail = self.consume_f(|c| c.is_token_parts()).unwrap_or("".into());
+        // 識別子の途中に空白は挟めない
+        let tail = self.consume_raw(|c| c.is_token_parts());
         Some(format!("{}{}", first, tail))
     }
     fn p_ident(&mut self, ope: Option<AddSub>, ident: String) -> ParseResult<Typed<Primary>> {
         let var = self.idents.get(&ident);
         if var.is_none() {
-            return Err(self.fail(format!("var {} undeclared", ident)));
+            if let Some(value) = self.enums.get(&ident) {
+                let node = (PrimaryNode::Num((*value, Type::Int)), Type::Int);
+                return Ok((Primary { ope, node }, Type::Int));
+            }
+            return self.p_global(ope, ident);
         }
         let v = var.unwrap();
+        // 式の型は修飾子を外したもの。宣言した型はIdentに残して書き込めるかの判定に使う
+        let type_ = v.type_.unqualified();
         Ok((
             Primary {
                 ope,
@@ -256,10 +616,33 @@ impl Parser<'_> {
                         offset: v.offset,
                         // refable, refで剥がして良い回数を持ちたい
                     })),
-                    v.type_.clone(),
+                    type_.clone(),
                 ),
             },
-            v.type_.clone(),
+            type_,
+        ))
+    }
+    fn p_global(&mut self, ope: Option<AddSub>, ident: String) -> ParseResult<Typed<Primary>> {
+        // 関数名は関数の型を持つグローバルなラベルとして扱う。値として読むとアドレスになる
+        let (name, declared) = match (self.globals.get(&ident), self.funcs.get(&ident)) {
+            (Some(g), _) => (g.ident.clone(), g.type_.clone()),
+            (None, Some(f)) => (ident, f.clone()),
+            (None, None) => return Err(self.fail(format!("var {} undeclared", ident))),
+        };
+        let type_ = declared.unqualified();
+        Ok((
+            Primary {
+                ope,
+                node: (
+                    PrimaryNode::Lv(Lvar::Global(Ident {
+                        name,
+                        _type_: declared,
+                        offset: 0,
+                    })),
+                    type_.clone(),
+                ),
+            },
+            type_,
         ))
     }
     fn for_test_is_func_available(&self, ident: String) -> bool {
@@ -272,27 +655,101 @@ impl Parser<'_> {
         if !available && f.is_none() {
             return Err(self.fail(format!("func {} is undefined", &ident)));
         }
-        let type_ = if available {
-            Type::Int
-        } else {
-            f.unwrap().clone()
+        let f = f.cloned();
+        let args = self.call_args()?;
+        let (type_, params) = match f.as_ref().and_then(|f| f.callee()) {
+            Some((ret, params)) => (ret.clone(), self.check_args(&ident, params, &args)?),
+            None => (Type::Int, self.check_args(&ident, &None, &args)?),
         };
-        let args = self.parenthesized(|p| {
-            p.loop_while(
-                |p, _| !p.check_top(")") && !p.empty(),
-                |p, _| p.consume(",").is_some(),
-                |p, _| p.expr(),
-            )
-        })?;
-        // TODO: argsの型チェックもしようね
+        let ret_slot = self.ret_slot(&type_);
         Ok((
             Primary {
                 ope,
-                node: (PrimaryNode::Fcall(Fcall { ident, args }), type_.clone()),
+                node: (
+                    PrimaryNode::Fcall(Fcall {
+                        ident,
+                        args,
+                        params,
+                        ret_slot,
+                    }),
+                    type_.clone(),
+                ),
             },
             type_,
         ))
     }
+    // 構造体を返す呼び出しは、戻り値を置く領域をフレームに確保する
+    fn ret_slot(&mut self, ret: &Type) -> Option<usize> {
+        let Type::Struct(_) = ret.base() else {
+            return None;
+        };
+        self.required_memory = align_to(self.required_memory + ret.sizeof(), ret.alignof());
+        Some(self.required_memory)
+    }
+    fn call_args(&mut self) -> ParseResult<Vec<Typed<Expr>>> {
+        self.parenthesized(|p| {
+            p.loop_while(
+                |p, _| !p.check_top(")") && !p.empty(),
+                |p, _| p.consume(",").is_some(),
+                |p, _| p.expr(),
+            )
+        })
+    }
+    // 引数の数と型を宣言と照らし合わせ、各引数を渡す型を返す。
+    // ()で宣言された関数は検査せず、整数拡張とfloatからdoubleへの拡張だけをして渡す
+    fn check_args(
+        &self,
+        name: &str,
+        params: &Option<Vec<Type>>,
+        args: &[Typed<Expr>],
+    ) -> ParseResult<Vec<Type>> {
+        let Some(params) = params else {
+            return Ok(args
+                .iter()
+                .map(|a| match a.1.decay() {
+                    Type::Float => Type::Double,
+                    t if t.is_integer() => t.promote(),
+                    t => t,
+                })
+                .collect());
+        };
+        if params.len() != args.len() {
+            return Err(self.fail(format!(
+                "{} expects {} arguments but {} given",
+                name,
+                params.len(),
+                args.len()
+            )));
+        }
+        for (i, (param, arg)) in params.iter().zip(args.iter()).enumerate() {
+            let a = arg.1.decay();
+            let ok = match (param, &a) {
+                (p, a) if p.is_arithmetic() && a.is_arithmetic() => true,
+                // ヌルポインタ定数はどのポインタにも渡せる
+                (Type::Ptr(_), a) if a.is_integer() => arg.0.eval() == Some(0),
+                // 指す先の修飾子は増やせる。減らすなら警告する
+                (Type::Ptr(p), Type::Ptr(a)) if p.base() == a.base() => {
+                    self.warn_discarded_qualifiers(
+                        &format!("passing argument {} of {}", i + 1, name),
+                        param,
+                        &arg.1,
+                    );
+                    true
+                }
+                (p, a) => p == a,
+            };
+            if !ok {
+                return Err(self.fail(format!(
+                    "argument {} of {} expects {:?} but {:?} given",
+                    i + 1,
+                    name,
+                    param,
+                    arg.1
+                )));
+            }
+        }
+        Ok(params.clone())
+    }
     fn primary(&mut self, ope: Option<AddSub>) -> ParseResult<Typed<Primary>> {
         self.dbg("primary".into());
         if self.empty() {
@@ -301,18 +758,37 @@ impl Parser<'_> {
         if self.check_top("(") {
             return self.p_exp(ope);
         }
+        if self.check_top("'") {
+            return self.p_char(ope);
+        }
+        if self.check_top("\"") {
+            return self.p_str(ope);
+        }
+        // .5のような浮動小数点数リテラル
+        if self.check_top(".")
+            && self
+                .rest()
+                .chars()
+                .nth(1)
+                .is_some_and(|c| c.is_ascii_digit())
+        {
+            return self.p_float(ope);
+        }
         // 0-9なら数値と決めつけてよいかは疑問の余地あり
-        if self.check_top_f(|c| c.is_numeric()) {
+        if self.check_top_f(|c| c.is_ascii_digit()) {
             return self.p_num(ope);
         }
-        let ident = self.consume_f(|c| c.is_token_parts());
+        let ident = self.get_ident();
         if ident.is_none() {
             return Err(self.fail("identity expected".into()));
         }
-        if self.check_top("(") {
-            self.fcall(ope, ident.unwrap())
+        // 同名の変数があれば、関数名でなくその値を呼び出す
+        let ident = ident.unwrap();
+        let is_var = self.idents.contains_key(&ident) || self.globals.contains_key(&ident);
+        if self.check_top("(") && !is_var {
+            self.fcall(ope, ident)
         } else {
-            self.p_ident(ope, ident.unwrap())
+            self.p_ident(ope, ident)
         }
     }
     fn unary(&mut self, ope: Option<MulDiv>) -> ParseResult<Typed<Unary>> {
@@ -320,31 +796,37 @@ impl Parser<'_> {
         if self.empty() {
             return Err(self.fail("+, -, num or expression expected".into()));
         }
-        if self.consume("*").is_some() {
+        if self
+            .consume_expect(|c| c.is_token_parts(), SIZEOF)
+            .is_some()
+        {
+            return self.sizeof(ope);
+        } else if self
+            .consume_expect(|c| c.is_token_parts(), ALIGNOF)
+            .is_some()
+        {
+            let type_ = self.parenthesized(|p| p.type_name())?;
+            return Ok(num_unary(ope, type_.alignof() as i64, self.read_lines));
+        } else if self.consume("*").is_some() {
             let unary = self.unary(ope)?;
             let t = unary.1.clone();
-            return match t {
-                Type::Ptr(inside) => Ok((
+            return match t.deref() {
+                Some(inside) => Ok((
                     Unary::Ptr(UnaryPtr {
                         ope: PtrOpe::Ref,
                         unary: Box::new(unary),
                     }),
-                    *inside,
+                    inside,
                 )),
-                Type::Array(inside) => {
-                    return Ok((
-                        Unary::Ptr(UnaryPtr {
-                            ope: PtrOpe::Ref,
-                            unary: Box::new(unary),
-                        }),
-                        inside.0,
-                    )); // TODO これで本当に大丈夫？
-                }
-                ty => Err(self.fail(format!("cannot get ref of type {:?}", ty))),
+                None => Err(self.fail(format!("cannot get ref of type {:?}", t))),
             };
         } else if self.consume("&").is_some() {
             let unary = self.unary(ope)?;
-            let t = unary.1.clone();
+            if unary.0.is_bit_field() {
+                return Err(self.fail("cannot take address of bit-field".into()));
+            }
+            // constな変数のアドレスはconstを指すポインタになる
+            let t = unary.0.object_type().unwrap_or(unary.1.clone());
             return Ok((
                 Unary::Ptr(UnaryPtr {
                     ope: PtrOpe::Deref,
@@ -360,22 +842,251 @@ impl Parser<'_> {
         } else {
             None
         };
+        if self.check_cast() {
+            return self.cast(ope, addsub);
+        }
         let prim = self.primary(addsub)?;
-        let arrs = self.array_access()?;
-        let type_ = if arrs.len() > 0 {
-            Type::Array(Box::new((prim.1.clone(), arrs.len())))
+        self.postfix(ope, prim)
+    }
+    // primaryの後ろに続く添字、関数呼び出し、メンバアクセス
+    fn postfix(&mut self, ope: Option<MulDiv>, prim: Typed<Primary>) -> ParseResult<Typed<Unary>> {
+        let line = self.read_lines;
+        // 3[a]はa[3]と同じ。整数の直後の添字には配列やポインタを書ける
+        let mut arrs = vec![];
+        let commuted = prim.1.is_integer() && self.consume("[").is_some();
+        if commuted {
+            let expr = self.expr()?;
+            if !matches!(expr.1.base(), Type::Array(_) | Type::Vla(_) | Type::Ptr(_)) {
+                return Err(self.fail(format!(
+                    "subscripted value {:?} is not an array or pointer",
+                    expr.1
+                )));
+            }
+            if self.consume("]").is_none() {
+                return Err(self.fail("bracket unbalanced".into()));
+            }
+            arrs.push(expr);
+        }
+        arrs.extend(self.array_access()?);
+        let base = if commuted {
+            arrs[0].1.unqualified()
         } else {
             prim.1.clone()
         };
-        Ok((
+        let type_ = self.subscript_type(base, arrs.len())?;
+        // 単項の+-を付けた整数は整数拡張される
+        let type_ = if prim.0.ope.is_some() && type_.is_integer() {
+            type_.promote()
+        } else {
+            type_
+        };
+        let mut unary = (
             Unary::Var(UnaryVar {
                 ope,
                 prim,
                 _arrs: arrs,
+                line,
+            }),
+            type_,
+        );
+        loop {
+            if self.check_top("(") {
+                let Some((ret, params)) = unary.1.callee().cloned() else {
+                    return Err(self.fail(format!(
+                        "called object type {:?} is not a function",
+                        unary.1
+                    )));
+                };
+                let args = self.call_args()?;
+                let name = unary.0.ident().cloned().unwrap_or("(expression)".into());
+                let params = self.check_args(&name, &params, &args)?;
+                let ret_slot = self.ret_slot(&ret);
+                let (ope, sign) = unary.0.take_prefix();
+                unary = (
+                    Unary::Call(UnaryCall {
+                        ope,
+                        sign,
+                        callee: Box::new(unary),
+                        args,
+                        params,
+                        ret_slot,
+                    }),
+                    ret,
+                );
+                continue;
+            }
+            let arrow = if self.consume("->").is_some() {
+                true
+            } else if self.consume(".").is_some() {
+                false
+            } else {
+                break;
+            };
+            let def = match (&unary.1, arrow) {
+                (Type::Struct(s), false) => Some(s.clone()),
+                (Type::Ptr(t), true) => match t.base() {
+                    Type::Struct(s) => Some(s.clone()),
+                    _ => None,
+                },
+                _ => None,
+            };
+            let Some(def) = def else {
+                let ope = if arrow { "->" } else { "." };
+                return Err(self.fail(format!("{} is not available for {:?}", ope, unary.1)));
+            };
+            let Some(name) = self.get_ident() else {
+                return Err(self.fail(IDENTITY_WANTED.into()));
+            };
+            let Some(member) = def.member(&name) else {
+                return Err(self.fail(format!("{:?} has no member named {}", def, name)));
+            };
+            let line = self.read_lines;
+            let arrs = self.array_access()?;
+            let type_ = match &member.type_ {
+                Type::Bits(_) if !arrs.is_empty() => {
+                    return Err(self.fail("subscripted value is a bit-field".into()));
+                }
+                Type::Bits(b) => bit_field_type(&b.0, b.1.width),
+                t => self.subscript_type(t.unqualified(), arrs.len())?,
+            };
+            let (ope, sign) = unary.0.take_prefix();
+            unary = (
+                Unary::Member(UnaryMember {
+                    ope,
+                    sign,
+                    unary: Box::new(unary),
+                    arrow,
+                    member,
+                    _arrs: arrs,
+                    line,
+                }),
+                type_,
+            );
+        }
+        Ok(unary)
+    }
+    fn check_cast(&mut self) -> bool {
+        let checkpoint = self.checkpoint();
+        let is_cast = self.consume("(").is_some() && self.check_type();
+        self.rollback(checkpoint);
+        is_cast
+    }
+    // キャストできるのは整数とポインタの間だけ
+    fn cast(&mut self, ope: Option<MulDiv>, sign: Option<AddSub>) -> ParseResult<Typed<Unary>> {
+        let to = self.parenthesized(|p| p.type_name())?;
+        if self.check_top("{") {
+            return self.compound_literal(ope, sign, to);
+        }
+        // キャストの結果は左辺値でないので修飾子は意味を持たない
+        let to = to.unqualified();
+        let unary = self.unary(None)?;
+        let from = unary.1.decay();
+        let scalar = |t: &Type| t.is_arithmetic() || t.is_pointer();
+        // ポインタと浮動小数点数の間では変換できない
+        let mixed = (to.is_pointer() && from.is_float()) || (to.is_float() && from.is_pointer());
+        if !scalar(&to) || !scalar(&from) || mixed {
+            return Err(self.fail(format!("cannot cast {:?} to {:?}", unary.1, to)));
+        }
+        let type_ = if sign.is_some() {
+            to.promote()
+        } else {
+            to.clone()
+        };
+        Ok((
+            Unary::Cast(UnaryCast {
+                ope,
+                sign,
+                to,
+                unary: Box::new(unary),
             }),
             type_,
         ))
     }
+    // (型){初期化子}は名前の無いローカル変数になる。変数と同じく左辺値で、後ろに添字やメンバアクセスを続けられる
+    fn compound_literal(
+        &mut self,
+        ope: Option<MulDiv>,
+        sign: Option<AddSub>,
+        type_: Type,
+    ) -> ParseResult<Typed<Unary>> {
+        if let Type::Vla(_) | Type::Func(_) = type_ {
+            return Err(self.fail(format!("compound literal has invalid type {:?}", type_)));
+        }
+        let mut elems = vec![];
+        let len = self.initializer(&type_, 0, &mut elems)?;
+        let type_ = complete_array(&type_, len);
+        if is_incomplete(&type_) {
+            return Err(self.fail(format!("compound literal has incomplete type {:?}", type_)));
+        }
+        self.required_memory = align_to(self.required_memory + type_.sizeof(), type_.alignof());
+        let def = VarDef {
+            ident: "(compound literal)".into(),
+            type_: type_.clone(),
+            offset: self.required_memory,
+            _arrs: vec![],
+        };
+        let node = (
+            PrimaryNode::Compound(Compound { def, elems }),
+            type_.unqualified(),
+        );
+        self.postfix(ope, (Primary { ope: sign, node }, type_.unqualified()))
+    }
+    // 添字1つにつき配列の次元を1つ剥がす
+    fn subscript_type(&mut self, mut type_: Type, count: usize) -> ParseResult<Type> {
+        for _ in 0..count {
+            type_ = match type_ {
+                Type::Array(_) | Type::Vla(_) | Type::Ptr(_) => type_.deref().unwrap(),
+                t => {
+                    return Err(self.fail(format!(
+                        "subscripted value {:?} is not an array or pointer",
+                        t
+                    )));
+                }
+            };
+        }
+        Ok(type_)
+    }
+    // sizeofは型の大きさを定数として返す。可変長配列だけは実行時に求める
+    fn sizeof(&mut self, ope: Option<MulDiv>) -> ParseResult<Typed<Unary>> {
+        let cp = self.checkpoint();
+        if self.consume("(").is_some() && self.check_type() {
+            self.rollback(cp);
+            let type_ = self.parenthesized(|p| p.type_name())?;
+            // sizeof (int[]){1, 2}は複合リテラルの大きさ
+            if !self.check_top("{") {
+                return Ok(num_unary(ope, type_.sizeof() as i64, self.read_lines));
+            }
+        }
+        self.rollback(cp);
+        let unary = self.unary(None)?;
+        if unary.0.is_bit_field() {
+            return Err(self.fail("sizeof applied to a bit-field".into()));
+        }
+        if let Type::Vla(_) = unary.1 {
+            return Ok((
+                Unary::Sizeof(UnarySizeof {
+                    ope,
+                    unary: Box::new(unary),
+                }),
+                Type::ULong,
+            ));
+        }
+        Ok(num_unary(ope, unary.1.sizeof() as i64, self.read_lines))
+    }
+    // 型名。sizeof(int*)やsizeof(int (*)[3])のように識別子を伴わない
+    fn type_name(&mut self) -> ParseResult<Type> {
+        let Some(base) = self.find_type()? else {
+            return Err(self.fail(TYPE_WANTED.into()));
+        };
+        let d = self.declarator(base)?;
+        if d.ident.is_some() {
+            return Err(self.fail("type name cannot have an identifier".into()));
+        }
+        if !d.vla.is_empty() {
+            return Err(self.fail("variable length array type name is not supported".into()));
+        }
+        Ok(d.type_)
+    }
     fn array_access(&mut self) -> ParseResult<Vec<Typed<Expr>>> {
         self.dbg("array_access".into());
         let mut arrs = vec![];
@@ -384,14 +1095,13 @@ impl Parser<'_> {
                 break;
             }
             let expr = self.expr()?;
-            // FIXME: 配列のアクセスによってIntになる場合に対応する
-            // if !expr.1.can_be_for_array_index() {
-            //     return Err(self.fail(format!(
-            //         "{} {:?}",
-            //         NOT_AVAILABLE_FOR_ARRAY_INDEX,
-            //         expr.1.clone()
-            //     )));
-            // }
+            if !expr.1.can_be_for_array_index() {
+                return Err(self.fail(format!(
+                    "{} {:?}",
+                    NOT_AVAILABLE_FOR_ARRAY_INDEX,
+                    expr.1.clone()
+                )));
+            }
             arrs.push(expr);
             // tをarrayに詰める
             // Arrayに詰まったないようをどう解釈するかはlvarかrvarかで変わるしoffsetは変数宣言時に変わる
@@ -423,13 +1133,26 @@ impl Parser<'_> {
                 p.unary(ope)
             },
         )?;
+        // 乗除算の結果は両辺を揃えた型になる
+        let result = unarys.iter().fold(type_.clone(), |t, (_, u)| {
+            if t.is_arithmetic() && u.is_arithmetic() {
+                t.common(u)
+            } else {
+                t
+            }
+        });
+        let result = if unarys.is_empty() {
+            type_.clone()
+        } else {
+            result
+        };
         Ok((
             Mul {
-                first: (una, type_.clone()),
+                first: (una, type_),
                 ope,
                 unarys,
             },
-            type_,
+            result,
         ))
     }
     fn add(&mut self, ope: Option<Compare>) -> ParseResult<Typed<Add>> {
@@ -450,7 +1173,7 @@ impl Parser<'_> {
             },
         )?;
 
-        if muls.len() == 0 {
+        if muls.is_empty() {
             return Ok((
                 Add {
                     first: (first, type_.clone()),
@@ -461,22 +1184,41 @@ impl Parser<'_> {
             ));
         }
         if !type_match(&type_, &muls.first().unwrap().1) {
-            Err(self.fail(format!(
+            return Err(self.fail(format!(
                 "bad operator usage {:?} {:?} {:?}",
                 type_,
                 muls.first().unwrap().0.ope,
                 muls.first().unwrap().1
-            )))
-        } else {
-            Ok((
-                Add {
-                    first: (first, type_.clone()),
-                    ope,
-                    muls,
-                },
-                type_,
-            ))
+            )));
+        }
+        // ポインタ同士の差はlong、ポインタと整数の加減算はポインタになる
+        let mut result = type_.decay();
+        for (m, t) in muls.iter() {
+            let t = t.decay();
+            if (result.is_pointer() && t.is_float()) || (result.is_float() && t.is_pointer()) {
+                return Err(self.fail(format!("invalid operands {:?} and {:?}", result, t)));
+            }
+            result = match (result.is_pointer(), t.is_pointer(), &m.ope) {
+                (true, true, Some(AddSub::Minus)) => Type::Long,
+                (true, true, _) => {
+                    return Err(self.fail(format!("cannot add {:?} and {:?}", result, t)));
+                }
+                (false, true, Some(AddSub::Minus)) => {
+                    return Err(self.fail(format!("cannot subtract {:?} from {:?}", t, result)));
+                }
+                (false, true, _) => t,
+                (true, false, _) => result,
+                _ => result.common(&t),
+            };
         }
+        Ok((
+            Add {
+                first: (first, type_),
+                ope,
+                muls,
+            },
+            result,
+        ))
     }
     fn relational(&mut self, ope: Option<Equals>) -> ParseResult<Typed<Relational>> {
         self.dbg("relational".into());
@@ -503,11 +1245,11 @@ impl Parser<'_> {
                 p.add(ope)
             },
         )?;
-        if adds.len() == 0 {
+        if adds.is_empty() {
             let t = first.1.clone();
             Ok((Relational { first, ope, adds }, t))
         } else {
-            if adds.first().is_none() {
+            if adds.is_empty() {
                 return Err(self.fail("compiler bug, relational.add accidentally empty".into()));
             }
             let un = adds.first().unwrap();
@@ -542,7 +1284,7 @@ impl Parser<'_> {
                 p.relational(ope)
             },
         )?;
-        if relationals.first().is_none() {
+        if relationals.is_empty() {
             Ok((
                 Equality {
                     first: (first, l_type.clone()),
@@ -551,7 +1293,14 @@ impl Parser<'_> {
                 l_type,
             ))
         } else {
-            if relationals.first().unwrap().1 != l_type {
+            let r_type = &relationals.first().unwrap().1;
+            let (l, r) = (l_type.decay(), r_type.decay());
+            // 整数同士、またはポインタと整数(ヌルポインタ定数)なら比較できる
+            let comparable = l == r
+                || (l.is_arithmetic() && r.is_arithmetic())
+                || (l.is_integer() && r.is_pointer())
+                || (l.is_pointer() && r.is_integer());
+            if !comparable {
                 Err(self.fail(format!(
                     "bad operation usage {:?} {:?} {:?}",
                     l_type,
@@ -584,79 +1333,516 @@ impl Parser<'_> {
                 rtype,
             ));
         }
+        if let Type::Array(_) | Type::Func(_) = rtype {
+            return Err(self.fail(format!("{:?} is not assignable", rtype)));
+        }
+        if eq
+            .unary()
+            .and_then(|u| u.object_type())
+            .is_some_and(|t| t.is_read_only())
+        {
+            return Err(self.fail("assignment of read-only location".into()));
+        }
+        let rvar = self.expr()?;
+        self.warn_discarded_qualifiers("assignment", &rtype, &rvar.1);
         Ok((
             Assign::Asgn(Asgn {
                 lvar: (eq, rtype.clone()),
-                rvar: Box::new(self.expr()?),
+                rvar: Box::new(rvar),
             }),
             rtype,
         ))
     }
-    fn lvar(&mut self) -> ParseResult<(usize, String)> {
-        self.dbg("lvar".into());
-        let ref_count = self.consume_f(|c| c == '*').unwrap_or("".into()).len();
-        let next_token = self.get_ident();
-        if next_token.is_none() {
-            return Err(self.fail(IDENTITY_WANTED.into()));
-        }
-        Ok((ref_count, next_token.unwrap()))
+    // ポインタの変換で指す先の修飾子が落ちるなら警告する
+    fn warn_discarded_qualifiers(&self, context: &str, to: &Type, from: &Type) {
+        let (Type::Ptr(to), Type::Ptr(from)) = (to.base(), from.decay()) else {
+            return;
+        };
+        let missing = to.qualifiers().missing(&from.qualifiers());
+        if !missing.is_empty() {
+            self.warn(format!(
+                "{} discards '{}' qualifier from pointer target type",
+                context,
+                missing.join(" ")
+            ));
+        }
     }
-    fn gen_type(&mut self, t: Type, ref_count: usize) -> Type {
-        if ref_count == 0 {
-            t
-        } else {
-            Type::Ptr(Box::new(self.gen_type(t, ref_count - 1)))
+    // 宣言子。*の後ろに識別子か括弧で囲んだ宣言子が来て、その後ろに配列や引数リストが付く。
+    // 抽象宣言子では識別子を省略できる
+    fn declarator(&mut self, base: Type) -> ParseResult<Declarator> {
+        self.dbg("declarator".into());
+        let mut base = base;
+        // *の後ろの修飾子はポインタ自身を修飾する
+        while self.consume("*").is_some() {
+            base = Type::Ptr(Box::new(base)).qualify(self.qualifiers());
+        }
+        if self.nested_declarator() {
+            // int (*p)[3]のpは外側の接尾辞を適用した型へのポインタになるので、
+            // 括弧の中は読み飛ばして接尾辞を先に読み、その型を基に括弧の中を読み直す
+            let start = self.checkpoint();
+            self.parenthesized(|p| p.declarator(Type::Int))?;
+            let Declarator { type_, vla, .. } = self.type_suffix(base)?;
+            if !vla.is_empty() {
+                return Err(self.fail("variable length array is not supported here".into()));
+            }
+            let end = self.checkpoint();
+            self.rollback(start);
+            let inner = self.parenthesized(|p| p.declarator(type_.clone()))?;
+            self.rollback(end);
+            return Ok(inner);
+        }
+        let ident = self.get_ident();
+        Ok(Declarator {
+            ident,
+            ..self.type_suffix(base)?
+        })
+    }
+    // (の後ろが引数リストでなく宣言子なら、括弧はまとまりを表す
+    fn nested_declarator(&mut self) -> bool {
+        let checkpoint = self.checkpoint();
+        let nested = self.consume("(").is_some()
+            && (self.check_top("*")
+                || self.check_top("(")
+                || (self.check_top_f(|c| c.is_token_first()) && !self.check_type()));
+        self.rollback(checkpoint);
+        nested
+    }
+    // 宣言子の接尾辞。引数リストなら関数、[]なら配列になる。配列の先頭の次元は省略できる
+    fn type_suffix(&mut self, base: Type) -> ParseResult<Declarator> {
+        if self.check_top("(") {
+            let checkpoint = self.checkpoint();
+            let unspecified = self.consume("(").is_some() && self.consume(")").is_some();
+            self.rollback(checkpoint);
+            let params = self.args()?;
+            // 引数や戻り値そのものの修飾子は関数の型に含めない
+            let types = params.iter().map(|p| p.type_.unqualified()).collect();
+            return Ok(Declarator {
+                ident: None,
+                type_: Type::Func(Box::new((
+                    base.unqualified(),
+                    (!unspecified).then_some(types),
+                ))),
+                vla: vec![],
+                params: Some(params),
+            });
+        }
+        let checkpoint = self.checkpoint();
+        let unsized_ = self.consume("[").is_some() && self.consume("]").is_some();
+        if !unsized_ {
+            self.rollback(checkpoint);
+        }
+        let dims = self.array_access()?;
+        let (type_, vla) = self.array_type(base, dims)?;
+        if unsized_ && !vla.is_empty() {
+            return Err(self.fail("variable length array is not supported here".into()));
+        }
+        Ok(Declarator {
+            ident: None,
+            type_: if unsized_ {
+                Type::Array(Box::new((type_, 0)))
+            } else {
+                type_
+            },
+            vla,
+            params: None,
+        })
+    }
+    // 要素数がすべて定数式なら固定長配列、そうでなければ可変長配列になる。
+    // 可変長配列の場合は実行時に評価する要素数の式を返す
+    fn array_type(
+        &mut self,
+        base: Type,
+        dims: Vec<Typed<Expr>>,
+    ) -> ParseResult<(Type, Vec<Typed<Expr>>)> {
+        if dims.is_empty() {
+            return Ok((base, dims));
+        }
+        let lens: Option<Vec<i64>> = dims.iter().map(|d| d.0.eval()).collect();
+        if lens.is_none() {
+            return Ok((Type::Vla(Box::new((base, dims.len()))), dims));
+        }
+        let mut type_ = base;
+        for len in lens.unwrap().into_iter().rev() {
+            if len < 0 {
+                return Err(self.fail(format!("size of array is negative: {}", len)));
+            }
+            type_ = Type::Array(Box::new((type_, len as usize)));
         }
+        Ok((type_, vec![]))
     }
-    fn def(&mut self) -> ParseResult<(Vec<VarDef>, Option<Assign>, Type)> {
+    fn def(&mut self) -> ParseResult<(Vec<VarInit>, Type)> {
         self.dbg("def".into());
-        let type_ = self.find_type();
+        if self
+            .consume_expect(|c| c.is_token_parts(), TYPEDEF)
+            .is_some()
+        {
+            return Ok((vec![], self.typedef_decl()?));
+        }
+        let type_ = self.find_type()?;
         if type_.is_none() {
             return Err(self.fail("type expected".into()));
         }
         let vardefs = self.loop_while(
-            |p, _| p.check_top_f(|c| c.is_token_first() || c == '*') && !p.empty(),
+            |p, _| p.check_top_f(|c| c.is_token_first() || c == '*' || c == '(') && !p.empty(),
             |p, _| p.consume(",").is_some(),
             |p, _| {
-                let (ref_count, ident) = p.lvar()?;
-                if p.idents.get(&ident).is_some() {
+                let d = p.declarator(type_.clone().unwrap())?;
+                let Some(ident) = d.ident else {
+                    return Err(p.fail(IDENTITY_WANTED.into()));
+                };
+                if p.scope.contains(&ident)
+                    && (p.idents.contains_key(&ident) || p.is_local_static(&ident))
+                {
                     return Err(p.fail(format!("multi definition for {}", ident)));
                 }
-                let mut type_ = p.gen_type(type_.clone().unwrap(), ref_count);
-                let _arrs = p.array_access()?;
-                type_ = if _arrs.len() > 0 {
-                    Type::Array(Box::new((type_.clone(), _arrs.len())))
-                } else {
-                    type_.clone()
-                };
-                // n次元配列の各次元の要素数は配列そのものへのポインタの上に格納する
-                p.required_memory += if _arrs.len() > 0 {
+                let (type_, _arrs) = (d.type_, d.vla);
+                if let Type::Func(_) = type_ {
+                    // ブロック内での関数の宣言。変数の領域は要らない
+                    p.funcs.insert(ident, type_);
+                    return Ok(None);
+                }
+                if is_incomplete(&type_) {
+                    return Err(p.fail(format!(
+                        "variable {} has incomplete type {:?}",
+                        ident, type_
+                    )));
+                }
+                p.declare(&ident)?;
+                // 可変長配列では、n次元配列の各次元の要素数を配列の実体へのポインタの上に格納する
+                let (size, align) = if !_arrs.is_empty() {
                     // 配列長の保持+配列の実体へのポインタ
-                    (_arrs.len() + 1) * IDENTITY_OFFSET
+                    ((_arrs.len() + 1) * IDENTITY_OFFSET, IDENTITY_OFFSET)
                 } else {
-                    type_.sizeof()
+                    (type_.sizeof(), type_.alignof())
                 };
-                let def = VarDef {
+                // rbpは16byte境界にあるので、rbpからのオフセットを揃えれば変数のアドレスも揃う
+                p.required_memory = align_to(p.required_memory + size, align);
+                let mut def = VarDef {
                     ident: ident.clone(),
                     offset: p.required_memory,
                     type_,
-                    _ref_count_: ref_count,
                     _arrs,
                 };
+                // 初期化子の中から自身を参照できるよう、初期化子を読む前に登録する
                 p.idents.insert(ident.clone(), def.clone());
-                Ok(def)
+                let init = if p.consume("=").is_none() {
+                    None
+                } else if !def._arrs.is_empty() {
+                    return Err(p.fail(format!(
+                        "variable-sized object {} may not be initialized",
+                        ident
+                    )));
+                } else {
+                    Some(p.local_init(&mut def)?)
+                };
+                // 修飾子は書き込めるかの判定にだけ使うので、コード生成には外した型を渡す
+                def.type_ = def.type_.unqualified();
+                Ok(Some((def, init)))
             },
         )?;
-        if self.consume("=").is_none() {
-            return Ok((vardefs, None, type_.unwrap()));
+        Ok((vardefs.into_iter().flatten().collect(), type_.unwrap()))
+    }
+    // ブロック内のstaticやexternの宣言
+    fn storage_def(&mut self, storage: &str) -> ParseResult<Type> {
+        let Some(type_) = self.find_type()? else {
+            return Err(self.fail(TYPE_WANTED.into()));
+        };
+        loop {
+            let d = self.declarator(type_.clone())?;
+            let Some(ident) = d.ident.clone() else {
+                return Err(self.fail(IDENTITY_WANTED.into()));
+            };
+            if self.scope.contains(&ident)
+                && (self.idents.contains_key(&ident) || self.is_local_static(&ident))
+            {
+                return Err(self.fail(format!("multi definition for {}", ident)));
+            }
+            self.declare(&ident)?;
+            match (&d.type_, storage) {
+                (Type::Func(_), STATIC) => {
+                    return Err(self.fail(format!("invalid storage class for function {}", ident)));
+                }
+                (Type::Func(_), _) => {
+                    self.funcs.insert(ident, d.type_);
+                }
+                // 外の大域変数を参照する。宣言はこの関数の中でだけ見える
+                (_, EXTERN) => {
+                    self.gvar(Some(EXTERN), d, ident)?;
+                }
+                _ => self.static_local(d, ident)?,
+            }
+            if self.consume(",").is_none() {
+                return Ok(type_);
+            }
+        }
+    }
+    // 関数内のstatic変数。実体は大域変数と同じく.dataか.bssに置き、初期化はプログラムの開始前に一度だけ行われる
+    fn static_local(&mut self, d: Declarator, ident: String) -> ParseResult<()> {
+        let mut type_ = d.type_;
+        if !d.vla.is_empty() {
+            return Err(self.fail(format!(
+                "static variable {} has variable length array type",
+                ident
+            )));
+        }
+        if is_incomplete(&type_) {
+            return Err(self.fail(format!(
+                "variable {} has incomplete type {:?}",
+                ident, type_
+            )));
+        }
+        let init = self.static_init(&mut type_)?;
+        let gvar = GVar {
+            ident: format!("{}.{}", ident, self.statics.len()),
+            type_,
+            init,
+            linkage: Linkage::Internal,
+            defined: true,
+        };
+        // 名前からはラベルを引けるよう、この関数の中だけで見える大域変数として登録する
+        self.globals.insert(ident, gvar.clone());
+        self.statics.push(gvar);
+        Ok(())
+    }
+    // 関数内のstatic変数は、名前とラベルが異なる大域変数として登録されている
+    fn is_local_static(&self, ident: &str) -> bool {
+        self.globals.get(ident).is_some_and(|g| g.ident != ident)
+    }
+    // 宣言子1つ分の初期化子。波括弧や文字列の初期化子は要素ごとに展開する
+    fn local_init(&mut self, v: &mut VarDef) -> ParseResult<Initializer> {
+        if !self.check_top("{") && !matches!(v.type_, Type::Array(_)) {
+            let (assign, type_) = self.assign()?;
+            self.warn_discarded_qualifiers("initialization", &v.type_, &type_);
+            return Ok(Initializer::Assign(assign));
+        }
+        let mut elems = vec![];
+        let len = self.initializer(&v.type_, 0, &mut elems)?;
+        // 長さを省略した配列の長さは要素数で決まるので、ここで領域を確保し直す
+        let complete = complete_array(&v.type_, len);
+        if v.type_ != complete {
+            self.required_memory =
+                align_to(self.required_memory + complete.sizeof(), complete.alignof());
+            v.type_ = complete;
+            v.offset = self.required_memory;
+            self.idents.insert(v.ident.clone(), v.clone());
+        }
+        Ok(Initializer::List(elems))
+    }
+    // 初期化子1つ分を読み、スカラの要素ごとに展開してelemsに加える。読んだ配列の要素数を返す
+    fn initializer(
+        &mut self,
+        t: &Type,
+        offset: usize,
+        elems: &mut Vec<InitElem>,
+    ) -> ParseResult<usize> {
+        // 初期化はconstなオブジェクトにも書ける
+        let t = &t.unqualified();
+        if self.check_top("{") {
+            return self.init_list(t, offset, elems, true);
+        }
+        if let Some(len) = self.init_string(t, offset, elems)? {
+            return Ok(len);
+        }
+        match t {
+            // 波括弧を省略した配列は、囲むリストの要素を必要なだけ読む
+            Type::Array(_) => self.init_list(t, offset, elems, false),
+            Type::Struct(_) => {
+                // 同じ型の構造体の値なら、それで丸ごと初期化する
+                let checkpoint = self.checkpoint();
+                let value = self.assign()?;
+                if value.1 != *t {
+                    self.rollback(checkpoint);
+                    return self.init_list(t, offset, elems, false);
+                }
+                let type_ = t.clone();
+                push_init(
+                    elems,
+                    InitElem {
+                        offset,
+                        type_,
+                        value: InitValue::Expr(value),
+                    },
+                );
+                Ok(1)
+            }
+            _ => {
+                let value = self.assign()?;
+                let type_ = t.clone();
+                push_init(
+                    elems,
+                    InitElem {
+                        offset,
+                        type_,
+                        value: InitValue::Expr(value),
+                    },
+                );
+                Ok(1)
+            }
+        }
+    }
+    // char配列は文字列リテラルで初期化できる。長さを省略していれば終端の\0までが入る長さになる
+    fn init_string(
+        &mut self,
+        t: &Type,
+        offset: usize,
+        elems: &mut Vec<InitElem>,
+    ) -> ParseResult<Option<usize>> {
+        let Type::Array(a) = t else {
+            return Ok(None);
+        };
+        if !matches!(a.0.base(), Type::Char | Type::UChar) || !self.check_top("\"") {
+            return Ok(None);
+        }
+        let (assign, _) = self.assign()?;
+        let Some(PrimaryNode::Str(s)) = assign.primary().map(|p| &p.node.0) else {
+            return Err(self.fail("invalid initializer for char array".into()));
+        };
+        let len = if a.1 == 0 { s.len() + 1 } else { a.1 };
+        let mut bytes = s.clone();
+        bytes.resize(len, 0);
+        let type_ = Type::Array(Box::new((a.0.clone(), len)));
+        push_init(
+            elems,
+            InitElem {
+                offset,
+                type_,
+                value: InitValue::Str(bytes),
+            },
+        );
+        Ok(Some(len))
+    }
+    // 配列や構造体の要素を順に読む。波括弧がなければ、要素が埋まるか囲むリストの終わりか指示子まで読む
+    fn init_list(
+        &mut self,
+        t: &Type,
+        offset: usize,
+        elems: &mut Vec<InitElem>,
+        braced: bool,
+    ) -> ParseResult<usize> {
+        if braced {
+            self.consume("{");
+            // スカラや文字列も波括弧で囲める
+            let single = if let Some(len) = self.init_string(t, offset, elems)? {
+                Some(len)
+            } else if !matches!(t, Type::Array(_) | Type::Struct(_)) {
+                Some(self.initializer(t, offset, elems)?)
+            } else {
+                None
+            };
+            if let Some(len) = single {
+                self.consume(",");
+                if self.consume("}").is_none() {
+                    return Err(self.fail("excess elements in initializer".into()));
+                }
+                return Ok(len);
+            }
+        }
+        let (mut index, mut count) = (0, 0);
+        loop {
+            if self.check_top("}") || (!braced && sub_object(t, index).is_none()) {
+                break;
+            }
+            if count > 0 {
+                let checkpoint = self.checkpoint();
+                if self.consume(",").is_none() {
+                    if braced {
+                        return Err(self.fail("',' or '}' expected in initializer".into()));
+                    }
+                    break;
+                }
+                // 末尾の,は許される
+                if self.check_top("}") || (!braced && self.is_designator()) {
+                    if !braced {
+                        self.rollback(checkpoint);
+                    }
+                    break;
+                }
+            }
+            if braced && self.is_designator() {
+                index = self.designation(t, offset, elems)?;
+            } else {
+                let Some((sub, sub_offset)) = sub_object(t, index) else {
+                    return Err(self.fail("excess elements in initializer".into()));
+                };
+                self.initializer(&sub, offset + sub_offset, elems)?;
+            }
+            index += 1;
+            count = count.max(index);
+        }
+        if braced && self.consume("}").is_none() {
+            return Err(self.fail("'}' expected at end of initializer".into()));
+        }
+        Ok(count)
+    }
+    fn is_designator(&mut self) -> bool {
+        let checkpoint = self.checkpoint();
+        let found = self.consume("[").is_some()
+            || (self.consume(".").is_some() && self.check_top_f(|c| c.is_token_first()));
+        self.rollback(checkpoint);
+        found
+    }
+    // [i]や.mの指示子で初期化する要素を選ぶ。指示子が続けば、選んだ要素の中をさらに選ぶ。選んだ要素の番号を返す
+    fn designation(
+        &mut self,
+        t: &Type,
+        offset: usize,
+        elems: &mut Vec<InitElem>,
+    ) -> ParseResult<usize> {
+        let (index, sub, sub_offset) = self.designator(t)?;
+        if self.is_designator() {
+            self.designation(&sub, offset + sub_offset, elems)?;
+        } else {
+            if self.consume("=").is_none() {
+                return Err(self.fail("'=' expected after designator".into()));
+            }
+            self.initializer(&sub, offset + sub_offset, elems)?;
+        }
+        Ok(index)
+    }
+    fn designator(&mut self, t: &Type) -> ParseResult<(usize, Type, usize)> {
+        if self.consume("[").is_some() {
+            let (index, _) = self.assign()?;
+            if self.consume("]").is_none() {
+                return Err(self.fail("] expected".into()));
+            }
+            let index = index.eval().filter(|i| *i >= 0);
+            return match (t, index) {
+                (Type::Array(_), None) => {
+                    Err(self.fail("array index in initializer is not a constant".into()))
+                }
+                (Type::Array(_), Some(i)) => match sub_object(t, i as usize) {
+                    Some((sub, sub_offset)) => Ok((i as usize, sub, sub_offset)),
+                    None => {
+                        Err(self.fail("array index in initializer exceeds array bounds".into()))
+                    }
+                },
+                _ => Err(self.fail("array index in non-array initializer".into())),
+            };
+        }
+        self.consume(".");
+        let Some(name) = self.get_ident() else {
+            return Err(self.fail(IDENTITY_WANTED.into()));
+        };
+        let Type::Struct(s) = t else {
+            return Err(self.fail(format!("field name {} not in struct initializer", name)));
+        };
+        let body = s.body.borrow();
+        let members = body.as_ref().map_or(&[][..], |b| &b.members);
+        match members.iter().position(|m| m.name == name) {
+            Some(i) => Ok((i, members[i].type_.clone(), members[i].offset)),
+            None => Err(self.fail(format!("{} has no member named {}", s.keyword(), name))),
         }
-        Ok((vardefs, Some(self.assign()?.0), type_.unwrap())) // TODO 本当に良い？
     }
     fn expr(&mut self) -> ParseResult<Typed<Expr>> {
         self.dbg("expr".into());
+        if let Some(storage) = self.storage_class() {
+            // 静的な記憶域の変数は関数の外に置くので、ここで初期化するコードは無い
+            let type_ = self.storage_def(&storage)?;
+            return Ok((Expr::VarAsgn(vec![]), type_));
+        }
         if self.check_type() {
-            let (a, b, type_) = self.def()?;
-            return Ok((Expr::VarAsgn(a, b), type_)); // FIXME: このtypeはint *xとしたときのint部分しか見ておらず、ポインタの部分は見られていないので、各変数の正しい型を見られていない。Generator側でうまく回避しているが、きちんとした形を模索すべき
+            let (defs, type_) = self.def()?;
+            return Ok((Expr::VarAsgn(defs), type_)); // FIXME: このtypeはint *xとしたときのint部分しか見ておらず、ポインタの部分は見られていないので、各変数の正しい型を見られていない。Generator側でうまく回避しているが、きちんとした形を模索すべき
         }
         let ret = self.consume_expect(|c| c.is_token_parts(), RETURN);
         let assign = self.assign()?;
@@ -705,9 +1891,9 @@ impl Parser<'_> {
             return Err(self.fail(") expected after 'for'".into()));
         }
         Ok(For {
-            init: init,
-            cond: cond,
-            step: step,
+            init,
+            cond,
+            step,
             stmt: Box::new(self.stmt()?),
         })
     }
@@ -729,10 +1915,23 @@ impl Parser<'_> {
         }
     }
     fn block(&mut self) -> ParseResult<Block> {
+        self.scoped_block(HashSet::new())
+    }
+    // scopeはブロックの始めから宣言されている名前。関数の本体では引数を同じブロックで宣言したものとして扱う
+    fn scoped_block(&mut self, scope: HashSet<String>) -> ParseResult<Block> {
         self.dbg("block".into());
         if self.consume("{").is_none() {
             return Err(self.fail(BLOCK_EXPECTED.into()));
         }
+        // ブロックで宣言したタグや名前はブロックを抜けると見えなくなる
+        let tags = self.tags.clone();
+        let tag_scope = std::mem::take(&mut self.tag_scope);
+        let enum_bodies = std::mem::take(&mut self.enum_bodies);
+        let idents = self.idents.clone();
+        let globals = self.globals.clone();
+        let typedefs = self.typedefs.clone();
+        let enums = self.enums.clone();
+        let scope = std::mem::replace(&mut self.scope, scope);
         let mut stmts = Vec::new();
         loop {
             if self.check_top("}") || self.empty() {
@@ -743,7 +1942,15 @@ impl Parser<'_> {
         if self.consume("}").is_none() {
             return Err(self.fail(BRACE_NOT_BALANCED.into()));
         }
-        Ok(Block { stmts: stmts })
+        self.tags = tags;
+        self.tag_scope = tag_scope;
+        self.enum_bodies = enum_bodies;
+        self.idents = idents;
+        self.globals = globals;
+        self.typedefs = typedefs;
+        self.enums = enums;
+        self.scope = scope;
+        Ok(Block { stmts })
     }
     fn stmt(&mut self) -> ParseResult<Statement> {
         self.dbg("stmt".into());
@@ -759,27 +1966,582 @@ impl Parser<'_> {
         if self.consume_expect(|c| c.is_token_parts(), WHILE).is_some() {
             return Ok(Statement::While(self.while_()?));
         }
+        if self.consume_expect(|c| c.is_token_parts(), GOTO).is_some() {
+            return self.goto();
+        }
         if self.check_top("{") {
             return Ok(Statement::MStmt(self.block()?));
         }
+        if self
+            .consume_expect(|c| c.is_token_parts(), STATIC_ASSERT)
+            .is_some()
+        {
+            self.static_assert()?;
+            return Ok(Statement::Nothing);
+        }
+        if self.consume_asm() {
+            return Ok(Statement::Asm(self.asm()?));
+        }
+        if let Some(label) = self.label()? {
+            return Ok(Statement::Label(label));
+        }
         let expr = self.expr()?;
         if self.consume(";").is_none() {
             return Err(self.fail("; expected".into()));
         }
-        return Ok(Statement::Stmt(Stmt { expr }));
+        Ok(Statement::Stmt(Stmt { expr }))
+    }
+    // _Static_assert(定数式, "メッセージ")。翻訳時に評価し、偽ならエラーにする。メッセージは省略できる
+    fn static_assert(&mut self) -> ParseResult<()> {
+        let (cond, msg) = self.parenthesized(|p| {
+            let (cond, _) = p.assign()?;
+            if p.consume(",").is_none() {
+                return Ok((cond, None));
+            }
+            let (msg, _) = p.assign()?;
+            match msg.primary().map(|p| &p.node.0) {
+                Some(PrimaryNode::Str(s)) => {
+                    Ok((cond, Some(String::from_utf8_lossy(s).into_owned())))
+                }
+                _ => Err(p.fail("string literal expected in static assertion".into())),
+            }
+        })?;
+        if self.consume(";").is_none() {
+            return Err(self.fail("; expected".into()));
+        }
+        match (cond.eval(), msg) {
+            (None, _) => Err(self.fail("expression in static assertion is not constant".into())),
+            (Some(0), Some(msg)) => Err(self.fail(format!("static assertion failed: \"{}\"", msg))),
+            (Some(0), None) => Err(self.fail("static assertion failed".into())),
+            _ => Ok(()),
+        }
+    }
+    fn consume_asm(&mut self) -> bool {
+        ASM.iter()
+            .any(|k| self.consume_expect(|c| c.is_token_parts(), k).is_some())
+    }
+    // asmの後ろの("テンプレート" : 出力 : 入力 : 破壊するレジスタ);。後ろの部分は省略できる
+    fn asm(&mut self) -> ParseResult<Asm> {
+        // 最適化をしないので、volatileは付いていてもいなくても同じ
+        if self
+            .consume_expect(|c| c.is_token_parts(), VOLATILE)
+            .is_none()
+        {
+            self.consume_expect(|c| c.is_token_parts(), ASM_VOLATILE);
+        }
+        if self.consume("(").is_none() {
+            return Err(self.fail("parenthesis expected".into()));
+        }
+        let mut asm = Asm {
+            template: self.string_literal()?,
+            outputs: vec![],
+            inputs: vec![],
+            clobbers: vec![],
+        };
+        if self.consume(":").is_some() {
+            asm.outputs = self.asm_operands(true)?;
+            if self.consume(":").is_some() {
+                asm.inputs = self.asm_operands(false)?;
+                if self.consume(":").is_some() {
+                    asm.clobbers = self.asm_clobbers()?;
+                }
+            }
+        }
+        if self.consume(")").is_none() {
+            return Err(self.fail("parenthesis unbalanced".into()));
+        }
+        if self.consume(";").is_none() {
+            return Err(self.fail("; expected".into()));
+        }
+        let count = asm.outputs.len() + asm.inputs.len();
+        if asm.expand(&vec![String::new(); count]).is_none() {
+            return Err(self.fail("operand number out of range in asm".into()));
+        }
+        Ok(asm)
+    }
+    // "制約"(式)の並び。出力は=か+で始まり、+なら入力も兼ねる
+    fn asm_operands(&mut self, output: bool) -> ParseResult<Vec<AsmOperand>> {
+        let mut operands = vec![];
+        if !self.check_top("\"") {
+            return Ok(operands);
+        }
+        loop {
+            let constraint = self.string_literal()?;
+            let mut chars = constraint.chars();
+            let modifier = if output { chars.next() } else { None };
+            if output && !matches!(modifier, Some('=' | '+')) {
+                return Err(self.fail(format!(
+                    "output operand constraint \"{}\" lacks '='",
+                    constraint
+                )));
+            }
+            let letter = match (chars.next(), chars.next()) {
+                (Some(c @ ('r' | 'a' | 'D' | 'm')), None) => c,
+                (Some('i'), None) if !output => 'i',
+                _ => return Err(self.fail(format!("invalid constraint \"{}\" in asm", constraint))),
+            };
+            let expr = self.parenthesized(|p| p.rvar())?;
+            // 出力とメモリの被演算子はアドレスを渡すので左辺値でなければならない
+            if (output || letter == 'm')
+                && (!expr.0.is_lvar() || matches!(expr.1, Type::Array(_) | Type::Func(_)))
+            {
+                return Err(self.fail("invalid lvalue in asm operand".into()));
+            }
+            let unary = expr.0.unary();
+            if unary.and_then(|u| u.bit_field()).is_some() {
+                return Err(self.fail("cannot take address of bit-field in asm operand".into()));
+            }
+            if output
+                && unary
+                    .and_then(|u| u.object_type())
+                    .is_some_and(|t| t.is_read_only())
+            {
+                return Err(self.fail("assignment of read-only location in asm output".into()));
+            }
+            if matches!(letter, 'r' | 'a' | 'D')
+                && matches!(expr.1, Type::Struct(_) | Type::LongDouble)
+            {
+                return Err(self.fail(format!("{:?} cannot be held in a register in asm", expr.1)));
+            }
+            if letter == 'i' && expr.0.eval().is_none() {
+                return Err(self.fail("asm operand with constraint \"i\" is not a constant".into()));
+            }
+            operands.push(AsmOperand {
+                constraint: letter,
+                read: !output || modifier == Some('+'),
+                expr,
+            });
+            if self.consume(",").is_none() {
+                return Ok(operands);
+            }
+        }
     }
+    fn asm_clobbers(&mut self) -> ParseResult<Vec<String>> {
+        let mut clobbers = vec![];
+        if !self.check_top("\"") {
+            return Ok(clobbers);
+        }
+        loop {
+            clobbers.push(self.string_literal()?);
+            if self.consume(",").is_none() {
+                return Ok(clobbers);
+            }
+        }
+    }
+    fn string_literal(&mut self) -> ParseResult<String> {
+        if !self.check_top("\"") {
+            return Err(self.fail("string literal expected".into()));
+        }
+        match self.p_str(None)?.0.node.0 {
+            PrimaryNode::Str(bytes) => Ok(String::from_utf8_lossy(&bytes).into_owned()),
+            _ => Err(self.fail("string literal expected".into())),
+        }
+    }
+    fn goto(&mut self) -> ParseResult<Statement> {
+        self.dbg("goto".into());
+        let ident = self.get_ident();
+        if ident.is_none() {
+            return Err(self.fail(IDENTITY_WANTED.into()));
+        }
+        let ident = ident.unwrap();
+        let undefined = self.fail(format!("label {} is undefined", ident));
+        self.gotos.push((ident.clone(), undefined));
+        if self.consume(";").is_none() {
+            return Err(self.fail("; expected".into()));
+        }
+        Ok(Statement::Goto(ident))
+    }
+    fn label(&mut self) -> ParseResult<Option<Label>> {
+        let checkpoint = self.checkpoint();
+        let ident = self.get_ident();
+        if ident.is_none() || self.consume(":").is_none() {
+            self.rollback(checkpoint);
+            return Ok(None);
+        }
+        let ident = ident.unwrap();
+        if !self.labels.insert(ident.clone()) {
+            return Err(self.fail(format!("duplicate label {}", ident)));
+        }
+        Ok(Some(Label {
+            ident,
+            stmt: Box::new(self.stmt()?),
+        }))
+    }
+    // 宣言の始まりかどうか。識別子がtypedef名なら型として扱う
     fn check_type(&mut self) -> bool {
-        TYPES.iter().any(|t| self.check_top(t))
+        let checkpoint = self.checkpoint();
+        let ident = self.get_ident();
+        self.rollback(checkpoint);
+        ident.is_some_and(|i| {
+            TYPES.contains(&i.as_str()) || i == TYPEDEF || self.typedef(&i).is_some()
+        })
     }
-    fn find_type(&mut self) -> Option<Type> {
-        let ty = TYPES.iter().fold(None, |acc, cur| match acc {
-            None => self.consume(cur),
-            Some(some) => Some(some),
-        })?;
+    fn typedef(&self, ident: &str) -> Option<Type> {
+        self.typedefs.get(ident).cloned()
+    }
+    // 変数・typedef名・列挙定数は同じ名前空間にある。同じブロックでは宣言し直せず、外側のブロックの同じ名前は隠れる
+    fn declare(&mut self, ident: &str) -> ParseResult<()> {
+        if !self.scope.insert(ident.into()) {
+            return Err(self.fail(format!("redefinition of {}", ident)));
+        }
+        self.idents.remove(ident);
+        self.typedefs.remove(ident);
+        self.enums.remove(ident);
+        Ok(())
+    }
+    // 修飾子は型指定子の前にも後ろにも書ける
+    fn find_type(&mut self) -> ParseResult<Option<Type>> {
+        let quals = self.qualifiers();
+        let Some(type_) = self.type_specifier()? else {
+            if quals.is_empty() {
+                return Ok(None);
+            }
+            return Err(self.fail(TYPE_WANTED.into()));
+        };
+        Ok(Some(type_.qualify(quals.merge(self.qualifiers()))))
+    }
+    fn qualifiers(&mut self) -> Qualifiers {
+        let mut quals = Qualifiers::default();
+        loop {
+            let checkpoint = self.checkpoint();
+            match self.get_ident().as_deref() {
+                Some(CONST) => quals.is_const = true,
+                Some(VOLATILE) => quals.is_volatile = true,
+                _ => {
+                    self.rollback(checkpoint);
+                    return quals;
+                }
+            }
+        }
+    }
+    fn type_specifier(&mut self) -> ParseResult<Option<Type>> {
+        let checkpoint = self.checkpoint();
+        let Some(ty) = self.get_ident() else {
+            return Ok(None);
+        };
         match ty.as_str() {
-            INT => Some(Type::Int),
+            INT | CHAR | SHORT | LONG | SIGNED | UNSIGNED | FLOAT | DOUBLE => {
+                self.rollback(checkpoint);
+                self.arithmetic_type().map(Some)
+            }
+            STRUCT => self.struct_type(false).map(Some),
+            UNION => self.struct_type(true).map(Some),
+            ENUM => self.enum_type().map(Some),
+            _ => {
+                let type_ = self.typedef(&ty);
+                if type_.is_none() {
+                    self.rollback(checkpoint);
+                }
+                Ok(type_)
+            }
+        }
+    }
+    // 算術型の指定子は順不同で組み合わせられる。charは符号付きとして扱う
+    fn arithmetic_type(&mut self) -> ParseResult<Type> {
+        let mut counts = HashMap::new();
+        let mut quals = Qualifiers::default();
+        loop {
+            // unsigned const intのように指定子の間にも修飾子を書ける
+            quals = quals.merge(self.qualifiers());
+            let checkpoint = self.checkpoint();
+            match self.get_ident() {
+                Some(s)
+                    if [INT, CHAR, SHORT, LONG, SIGNED, UNSIGNED, FLOAT, DOUBLE]
+                        .contains(&s.as_str()) =>
+                {
+                    *counts.entry(s).or_insert(0) += 1;
+                }
+                _ => {
+                    self.rollback(checkpoint);
+                    break;
+                }
+            }
+        }
+        let count = |s: &str| counts.get(s).copied().unwrap_or(0);
+        let unsigned = count(UNSIGNED) == 1;
+        let signedness = count(SIGNED) + count(UNSIGNED);
+        let type_ = match (count(CHAR), count(SHORT), count(LONG), count(INT)) {
+            _ if signedness > 1 => None,
+            // 浮動小数点数型は符号の指定や他の指定子と組み合わせられない
+            _ if count(FLOAT) + count(DOUBLE) > 0 => {
+                match (
+                    count(FLOAT),
+                    count(DOUBLE),
+                    count(LONG),
+                    signedness,
+                    counts.len(),
+                ) {
+                    (1, 0, 0, 0, 1) => Some(Type::Float),
+                    (0, 1, 0, 0, 1) => Some(Type::Double),
+                    (0, 1, 1, 0, 2) => Some(Type::LongDouble),
+                    _ => None,
+                }
+            }
+            (1, 0, 0, 0) => Some(if unsigned { Type::UChar } else { Type::Char }),
+            (0, 1, 0, 0 | 1) => Some(if unsigned { Type::UShort } else { Type::Short }),
+            (0, 0, 0, 0 | 1) => Some(if unsigned { Type::UInt } else { Type::Int }),
+            (0, 0, 1, 0 | 1) => Some(if unsigned { Type::ULong } else { Type::Long }),
+            (0, 0, 2, 0 | 1) => Some(if unsigned {
+                Type::ULongLong
+            } else {
+                Type::LongLong
+            }),
             _ => None,
+        };
+        type_
+            .map(|t| t.qualify(quals))
+            .ok_or_else(|| self.fail("invalid combination of type specifiers".into()))
+    }
+    // typedefの後ろの型と宣言子。宣言子の名前を型の別名として登録する
+    fn typedef_decl(&mut self) -> ParseResult<Type> {
+        let Some(base) = self.find_type()? else {
+            return Err(self.fail(TYPE_WANTED.into()));
+        };
+        while !self.check_top(";") && !self.empty() {
+            let d = self.declarator(base.clone())?;
+            let Some(ident) = d.ident else {
+                return Err(self.fail(IDENTITY_WANTED.into()));
+            };
+            if !d.vla.is_empty() {
+                return Err(self.fail("variable length array typedef is not supported".into()));
+            }
+            let type_ = d.type_;
+            // 同じブロックでも同じ型への再定義は許される
+            match self.typedefs.get(&ident) {
+                Some(t) if self.scope.contains(&ident) => {
+                    if *t != type_ {
+                        return Err(self.fail(format!("conflicting types for typedef {}", ident)));
+                    }
+                }
+                _ => self.declare(&ident)?,
+            }
+            self.typedefs.insert(ident, type_);
+            if self.consume(",").is_none() {
+                break;
+            }
+        }
+        Ok(base)
+    }
+    // struct/unionの後ろ。タグのみなら既存の型を参照し、未知のタグなら不完全型として登録する
+    fn struct_type(&mut self, is_union: bool) -> ParseResult<Type> {
+        let tag = self.get_ident();
+        // 外側のブロックのタグは、本体付きで定義するかstruct S;と宣言すると新しい型で隠れる
+        let shadow = tag.as_ref().is_some_and(|t| !self.tag_scope.contains(t))
+            && (self.check_top("{") || self.check_top(";"));
+        // 構造体・共用体・列挙型のタグは同じ名前空間を共有する
+        let known = match tag
+            .as_ref()
+            .filter(|_| !shadow)
+            .and_then(|t| self.tags.get(t))
+        {
+            Some(Type::Struct(def)) if def.is_union == is_union => Some(def.clone()),
+            Some(_) => {
+                let tag = tag.unwrap();
+                return Err(self.fail(format!("{} is redeclared with a different kind", tag)));
+            }
+            None => None,
+        };
+        if !self.check_top("{") {
+            let Some(tag) = tag else {
+                return Err(self.fail("tag or { expected".into()));
+            };
+            if let Some(def) = known {
+                return Ok(Type::Struct(def));
+            }
+            let def = Rc::new(StructDef::new(tag.clone(), is_union));
+            self.tag_scope.insert(tag.clone());
+            self.tags.insert(tag, Type::Struct(def.clone()));
+            return Ok(Type::Struct(def));
+        }
+        // 先に登録しておくことで、メンバから自分自身へのポインタを使える
+        let def = match known {
+            Some(def) if def.is_complete() => {
+                return Err(self.fail(format!("redefinition of {:?}", def)));
+            }
+            Some(def) => def,
+            None => Rc::new(StructDef::new(tag.clone().unwrap_or_default(), is_union)),
+        };
+        if let Some(tag) = tag {
+            self.tag_scope.insert(tag.clone());
+            self.tags.insert(tag, Type::Struct(def.clone()));
+        }
+        self.consume("{");
+        let members = self.struct_members()?;
+        if self.consume("}").is_none() {
+            return Err(self.fail(BRACE_NOT_BALANCED.into()));
+        }
+        let mut body = StructBody {
+            members: vec![],
+            size: 0,
+            align: 1,
+        };
+        // 各メンバは自身のアラインメントに揃えて置き、全体の大きさは最大のアラインメントの倍数にする。
+        // 共用体ではすべてのメンバを先頭に重ねるので、大きさは最大のメンバで決まる
+        let mut bits: usize = 0; // 使い終えた位置をビット単位で持つ
+        for (name, type_, width) in members {
+            if let Some(name) = &name
+                && body.members.iter().any(|m| m.name == *name)
+            {
+                return Err(self.fail(format!("duplicate member {}", name)));
+            }
+            let Some(width) = width else {
+                let offset = if is_union {
+                    0
+                } else {
+                    align_to(bits.div_ceil(8), type_.alignof())
+                };
+                bits = bits.max((offset + type_.sizeof()) * 8);
+                body.align = body.align.max(type_.alignof());
+                body.members.push(Member {
+                    name: name.unwrap(),
+                    type_,
+                    offset,
+                });
+                continue;
+            };
+            // ビットフィールドは宣言した型の境界をまたがないよう詰めて置く。幅0なら次の境界まで進める
+            let unit = type_.sizeof() * 8;
+            let start = if is_union {
+                0
+            } else if width == 0 || bits / unit != (bits + width - 1) / unit {
+                align_to(bits, unit)
+            } else {
+                bits
+            };
+            bits = bits.max(start + width);
+            // 名前の無いビットフィールドは構造体のアラインメントに影響しない
+            let Some(name) = name else {
+                continue;
+            };
+            body.align = body.align.max(type_.alignof());
+            let offset = start / unit * type_.sizeof();
+            let shift = start - offset * 8;
+            body.members.push(Member {
+                name,
+                type_: Type::Bits(Box::new((type_, BitField { shift, width }))),
+                offset,
+            });
+        }
+        body.size = align_to(bits.div_ceil(8), body.align);
+        *def.body.borrow_mut() = Some(body);
+        Ok(Type::Struct(def))
+    }
+    // enumの後ろ。列挙型の変数はintとして扱う
+    fn enum_type(&mut self) -> ParseResult<Type> {
+        let tag = self.get_ident();
+        if let Some(tag) = tag.as_ref() {
+            let shadow = !self.tag_scope.contains(tag) && self.check_top("{");
+            match self.tags.get(tag) {
+                Some(Type::Int) | None => {}
+                Some(_) if shadow => {}
+                Some(_) => {
+                    return Err(self.fail(format!("{} is redeclared with a different kind", tag)));
+                }
+            }
+            // 同じブロックで列挙定数の並びを2度書くことはできない
+            if self.check_top("{") && !self.enum_bodies.insert(tag.clone()) {
+                return Err(self.fail(format!("redefinition of {}", tag)));
+            }
+            self.tag_scope.insert(tag.clone());
+            self.tags.insert(tag.clone(), Type::Int);
+        }
+        if self.consume("{").is_none() {
+            if tag.is_none() {
+                return Err(self.fail("tag or { expected".into()));
+            }
+            return Ok(Type::Int);
+        }
+        // 値を省略した列挙定数は直前の値+1になる
+        let mut value = 0;
+        while !self.check_top("}") && !self.empty() {
+            let Some(name) = self.get_ident() else {
+                return Err(self.fail(IDENTITY_WANTED.into()));
+            };
+            self.declare(&name)?;
+            if self.consume("=").is_some() {
+                let (assign, _) = self.assign()?;
+                let Some(v) = assign.eval() else {
+                    return Err(self.fail(format!("value of {} is not a constant", name)));
+                };
+                value = v;
+            }
+            self.enums.insert(name, value);
+            value = value.wrapping_add(1);
+            if self.consume(",").is_none() {
+                break;
+            }
+        }
+        if self.consume("}").is_none() {
+            return Err(self.fail(BRACE_NOT_BALANCED.into()));
+        }
+        Ok(Type::Int)
+    }
+    fn struct_members(&mut self) -> ParseResult<Vec<MemberDecl>> {
+        let mut members = vec![];
+        while !self.check_top("}") && !self.empty() {
+            if self
+                .consume_expect(|c| c.is_token_parts(), STATIC_ASSERT)
+                .is_some()
+            {
+                self.static_assert()?;
+                continue;
+            }
+            let Some(base) = self.find_type()? else {
+                return Err(self.fail(TYPE_WANTED.into()));
+            };
+            loop {
+                let d = self.declarator(base.clone())?;
+                if self.consume(":").is_some() {
+                    let width = self.bit_width(&d)?;
+                    members.push((d.ident, d.type_, Some(width)));
+                    if self.consume(",").is_none() {
+                        break;
+                    }
+                    continue;
+                }
+                let Some(name) = d.ident else {
+                    return Err(self.fail(IDENTITY_WANTED.into()));
+                };
+                if !d.vla.is_empty() {
+                    return Err(self.fail("variable length array member is not supported".into()));
+                }
+                let type_ = d.type_;
+                if let Type::Func(_) = type_ {
+                    return Err(self.fail(format!("member {} is declared as a function", name)));
+                }
+                if is_incomplete(&type_) {
+                    return Err(
+                        self.fail(format!("member {} has incomplete type {:?}", name, type_))
+                    );
+                }
+                members.push((Some(name), type_, None));
+                if self.consume(",").is_none() {
+                    break;
+                }
+            }
+            if self.consume(";").is_none() {
+                return Err(self.fail("; expected".into()));
+            }
         }
+        Ok(members)
+    }
+    // ビットフィールドの幅。整数型の大きさまでの定数で、0なら名前を付けられない
+    fn bit_width(&mut self, d: &Declarator) -> ParseResult<usize> {
+        let name = d.ident.clone().unwrap_or("(anonymous)".into());
+        if !d.type_.is_integer() {
+            return Err(self.fail(format!("bit-field {} has invalid type {:?}", name, d.type_)));
+        }
+        let (width, _) = self.assign()?;
+        let Some(width) = width.eval() else {
+            return Err(self.fail(format!("bit-field {} width not an integer constant", name)));
+        };
+        if width < 0 || width as usize > d.type_.sizeof() * 8 {
+            return Err(self.fail(format!("width of bit-field {} is invalid", name)));
+        }
+        if width == 0 && d.ident.is_some() {
+            return Err(self.fail(format!("zero width for bit-field {}", name)));
+        }
+        Ok(width as usize)
     }
     fn loop_while<T>(
         &mut self,
@@ -819,75 +2581,348 @@ impl Parser<'_> {
     }
     fn args(&mut self) -> ParseResult<Vec<VarDef>> {
         self.dbg("args".into());
-        self.parenthesized(|p| {
+        let mut args = self.parenthesized(|p| {
+            if p.void_params() {
+                return Ok(vec![]);
+            }
             p.loop_while(
                 |p, _| !p.check_top(")") && !p.empty(),
                 |p, _| p.consume(",").is_some(),
-                |p, count| {
-                    let type_ = p.find_type();
-                    if type_.is_none() {
+                |p, _| {
+                    let Some(base) = p.find_type()? else {
                         return Err(p.fail(TYPE_WANTED.into()));
+                    };
+                    // プロトタイプ宣言では引数名を省略できる
+                    let d = p.declarator(base)?;
+                    if !d.vla.is_empty() {
+                        return Err(
+                            p.fail("variable length array parameter is not supported".into())
+                        );
                     }
-                    let (ref_count, ident) = p.lvar()?;
-
+                    // 配列や関数の引数はポインタに読み替える
+                    let type_ = match d.type_ {
+                        Type::Array(t) => Type::Ptr(Box::new(t.0)),
+                        t @ Type::Func(_) => Type::Ptr(Box::new(t)),
+                        t => t,
+                    };
                     Ok(VarDef {
-                        ident: ident,
-                        type_: type_.unwrap(),
-                        _ref_count_: ref_count,
-                        offset: (count + 1) * IDENTITY_OFFSET, // TODO 適切な大きさで確保する
-                        _arrs: vec![], // 関数の引数には配列アクセスっぽい記載はないはず
+                        ident: d.ident.unwrap_or_default(),
+                        type_,
+                        offset: 0,
+                        _arrs: vec![], // 配列の引数はポインタに読み替えるので可変長配列にはならない
                     })
                 },
             )
-        })
-    }
-
-    fn fdef(&mut self) -> ParseResult<Fdef> {
-        self.dbg("fdef".into());
-        let type_ = self.find_type();
-        if type_.is_none() {
-            return Err(self.fail(TYPE_WANTED.into()));
-        }
-        let ident = self.get_ident();
-        if ident.is_none() {
-            return Err(self.fail(IDENTITY_WANTED.into()));
+        })?;
+        // 引数の領域は最低でも8byteずつ確保する
+        let mut offset = 0;
+        for arg in args.iter_mut() {
+            let size = arg.type_.sizeof().max(IDENTITY_OFFSET);
+            offset = align_to(offset + size, arg.type_.alignof().max(IDENTITY_OFFSET));
+            arg.offset = offset;
         }
-        let args = self.args()?;
-        let mut idents = HashMap::new();
-        for arg in args.iter() {
-            idents.insert(arg.ident.clone(), arg.clone());
+        Ok(args)
+    }
+    // (void)は引数なし
+    fn void_params(&mut self) -> bool {
+        let checkpoint = self.checkpoint();
+        if self.consume_expect(|c| c.is_token_parts(), VOID).is_some() && self.check_top(")") {
+            return true;
         }
-        self.funcs.insert(ident.clone().unwrap(), type_.unwrap());
+        self.rollback(checkpoint);
+        false
+    }
+    fn fdef(
+        &mut self,
+        type_: Type,
+        ident: String,
+        args: Vec<VarDef>,
+        linkage: Linkage,
+    ) -> ParseResult<Fdef> {
+        self.dbg("fdef".into());
+        let ret = type_.callee().unwrap().0.clone();
+        self.funcs.insert(ident.clone(), type_);
+        let mut required_memory = args.last().map_or(0, |v| v.offset);
+        let ret_slot = if let Type::Struct(_) = ret.base() {
+            required_memory += IDENTITY_OFFSET;
+            Some(required_memory)
+        } else {
+            None
+        };
         let mut child = Parser {
             index: self.index,
             input: self.input,
             funcs: self.funcs.clone(),
-            required_memory: args.last().map_or(0, |v| v.offset),
-            idents,
+            required_memory,
+            idents: HashMap::new(),
+            globals: self.globals.clone(),
+            tags: self.tags.clone(),
+            tag_scope: HashSet::new(),
+            enum_bodies: HashSet::new(),
+            enums: self.enums.clone(),
+            typedefs: self.typedefs.clone(),
+            scope: HashSet::new(),
             read_lines: self.read_lines,
             line_index: self.line_index,
+            labels: HashSet::new(),
+            gotos: Vec::new(),
+            func_linkages: self.func_linkages.clone(),
+            statics: std::mem::take(&mut self.statics),
         };
-        let fimpl = child.block()?;
+        // 引数は本体のブロックで宣言した変数と同じく、外側のtypedef名を隠す
+        for arg in args.iter() {
+            child.declare(&arg.ident)?;
+            child.idents.insert(arg.ident.clone(), arg.clone());
+        }
+        let params = std::mem::take(&mut child.scope);
+        let fimpl = child.scoped_block(params)?;
+        self.statics = std::mem::take(&mut child.statics);
         self.index = child.index;
         self.read_lines = child.read_lines;
         self.line_index = child.line_index;
+        if let Some(pos) = child
+            .gotos
+            .iter()
+            .position(|(label, _)| !child.labels.contains(label))
+        {
+            return Err(child.gotos.swap_remove(pos).1);
+        }
 
         Ok(Fdef {
-            ident: ident.unwrap(),
+            ret,
+            ident,
             fimpl,
-            args,
+            args: args
+                .into_iter()
+                .map(|a| VarDef {
+                    type_: a.type_.unqualified(),
+                    ..a
+                })
+                .collect(),
             required_memory: child.required_memory,
+            linkage,
+            ret_slot,
+        })
+    }
+    fn storage_class(&mut self) -> Option<String> {
+        self.consume_expect(|c| c.is_token_parts(), STATIC)
+            .or_else(|| self.consume_expect(|c| c.is_token_parts(), EXTERN))
+    }
+    fn gvar(&mut self, storage: Option<&str>, d: Declarator, ident: String) -> ParseResult<GVar> {
+        let mut type_ = d.type_;
+        if !d.vla.is_empty() {
+            return Err(self.fail("variable length array at file scope".into()));
+        }
+        // externの宣言だけなら、中身のわからない構造体でもよい
+        if storage != Some(EXTERN) && is_incomplete(&type_) {
+            return Err(self.fail(format!(
+                "variable {} has incomplete type {:?}",
+                ident, type_
+            )));
+        }
+        let init = self.static_init(&mut type_)?;
+        let mut gvar = GVar {
+            ident: ident.clone(),
+            type_,
+            linkage: if storage == Some(STATIC) {
+                Linkage::Internal
+            } else {
+                Linkage::External
+            },
+            defined: storage != Some(EXTERN) || init.is_some(),
+            init,
+        };
+        // 同じ変数の宣言は何度でも書けるが、型が一致していて初期化が高々1回である必要がある
+        if let Some(prev) = self.globals.get(&ident) {
+            if prev.type_ != gvar.type_ {
+                return Err(self.fail(format!("conflicting types for {}", ident)));
+            }
+            if prev.init.is_some() && gvar.init.is_some() {
+                return Err(self.fail(format!("redefinition of {}", ident)));
+            }
+            if storage == Some(EXTERN) {
+                gvar.linkage = prev.linkage.clone();
+            } else if prev.linkage != gvar.linkage {
+                return Err(self.fail(format!("conflicting linkage for {}", ident)));
+            }
+            gvar.init = gvar.init.or(prev.init.clone());
+            gvar.defined |= prev.defined;
+        }
+        self.globals.insert(ident, gvar.clone());
+        Ok(gvar)
+    }
+    // 静的な記憶域を持つ変数の初期化子。長さを省略した配列はここで長さが決まる
+    fn static_init(&mut self, type_: &mut Type) -> ParseResult<Option<GInit>> {
+        Ok(if self.consume("=").is_none() {
+            None
+        } else if self.check_top("{") || matches!(type_, Type::Array(_)) {
+            let mut elems = vec![];
+            let len = self.initializer(type_, 0, &mut elems)?;
+            *type_ = complete_array(type_, len);
+            // 要素はすべて定数で、位置の順に並べて置く
+            let mut items = vec![];
+            // ビットフィールドは同じバイトを分け合うので、バイトごとに値を組み立ててから置く
+            let mut bytes: HashMap<usize, u8> = HashMap::new();
+            for e in elems {
+                let init = match e.value {
+                    InitValue::Expr((assign, _)) => match &e.type_ {
+                        Type::Bits(b) => {
+                            let GInit::Int(value) = self.const_init(&b.0, &assign)? else {
+                                return Err(self.fail("initializer element is not constant".into()));
+                            };
+                            for i in 0..b.1.width {
+                                let bit = b.1.shift + i;
+                                *bytes.entry(e.offset + bit / 8).or_default() |=
+                                    ((value >> i & 1) as u8) << (bit % 8);
+                            }
+                            continue;
+                        }
+                        t => self.const_init(t, &assign)?,
+                    },
+                    InitValue::Str(s) => GInit::Bytes(s),
+                };
+                items.push((e.offset, e.type_, init));
+            }
+            items.extend(
+                bytes
+                    .into_iter()
+                    .map(|(offset, b)| (offset, Type::UChar, GInit::Int(b as i64))),
+            );
+            items.sort_by_key(|i| i.0);
+            Some(GInit::List(items))
+        } else {
+            let (assign, _) = self.assign()?;
+            Some(self.const_init(&type_.unqualified(), &assign)?)
         })
     }
+    // 関数のリンケージ。一度staticで宣言した関数は、以降の宣言でstaticを省いても内部リンケージのまま
+    fn func_linkage(&mut self, ident: &str, storage: Option<&str>) -> ParseResult<Linkage> {
+        let linkage = match (self.func_linkages.get(ident), storage) {
+            (Some(Linkage::External), Some(STATIC)) => {
+                return Err(self.fail(format!(
+                    "static declaration of {} follows non-static declaration",
+                    ident
+                )));
+            }
+            (Some(prev), _) => prev.clone(),
+            (None, Some(STATIC)) => Linkage::Internal,
+            (None, _) => Linkage::External,
+        };
+        self.func_linkages.insert(ident.into(), linkage.clone());
+        Ok(linkage)
+    }
+    // 大域変数の初期値。コンパイル時に値が決まる必要がある
+    fn const_init(&self, type_: &Type, assign: &Assign) -> ParseResult<GInit> {
+        if let (Type::LongDouble, Some(value)) = (type_, assign.eval_ldouble()) {
+            Ok(GInit::LDouble(value))
+        } else if let Some(value) = assign.eval_float(type_).filter(|_| type_.is_float()) {
+            // 浮動小数点数はビット列をそのまま置く
+            Ok(GInit::Int(match type_ {
+                Type::Float => (value as f32).to_bits() as i64,
+                _ => value.to_bits() as i64,
+            }))
+        } else if let Some(value) = assign
+            .eval_as(type_)
+            .filter(|_| !matches!(type_, Type::Struct(_)))
+        {
+            Ok(GInit::Int(value))
+        } else if let Some((label, offset)) = assign.eval_addr().filter(|_| type_.is_pointer()) {
+            Ok(GInit::Addr(label, offset))
+        } else if let Some(PrimaryNode::Str(s)) = assign.primary().map(|p| &p.node.0) {
+            Ok(GInit::Str(s.clone()))
+        } else {
+            Err(self.fail("initializer element is not constant".into()))
+        }
+    }
     fn program(&mut self) -> ParseResult<Program> {
         let mut fdefs = Vec::new();
+        let mut gvar_order: Vec<String> = Vec::new();
+        let mut asms = Vec::new();
         loop {
             if self.empty() {
                 break;
             }
-            fdefs.push(self.fdef()?);
+            if self
+                .consume_expect(|c| c.is_token_parts(), TYPEDEF)
+                .is_some()
+            {
+                self.typedef_decl()?;
+                if self.consume(";").is_none() {
+                    return Err(self.fail("; expected".into()));
+                }
+                continue;
+            }
+            if self
+                .consume_expect(|c| c.is_token_parts(), STATIC_ASSERT)
+                .is_some()
+            {
+                self.static_assert()?;
+                continue;
+            }
+            // ファイルスコープのasmは被演算子を持てない
+            if self.consume_asm() {
+                let asm = self.asm()?;
+                if !asm.is_basic() {
+                    return Err(self.fail("asm at file scope cannot have operands".into()));
+                }
+                asms.push(asm.template);
+                continue;
+            }
+            let storage = self.storage_class();
+            let type_ = self.find_type()?;
+            if type_.is_none() {
+                return Err(self.fail(TYPE_WANTED.into()));
+            }
+            let type_ = type_.unwrap();
+            // struct S {...}; のように型の宣言だけのこともある
+            if self.consume(";").is_some() {
+                continue;
+            }
+            let mut first = true;
+            loop {
+                let d = self.declarator(type_.clone())?;
+                let Some(ident) = d.ident.clone() else {
+                    return Err(self.fail(IDENTITY_WANTED.into()));
+                };
+                if let Type::Func(_) = &d.type_ {
+                    let linkage = self.func_linkage(&ident, storage.as_deref())?;
+                    if first && self.check_top("{") {
+                        fdefs.push(self.fdef(
+                            d.type_,
+                            ident,
+                            d.params.unwrap_or_default(),
+                            linkage,
+                        )?);
+                        break;
+                    }
+                    // プロトタイプ宣言。実体は後続の定義か他のオブジェクトにある
+                    self.funcs.insert(ident, d.type_);
+                } else {
+                    // 大域変数は何度でも宣言できるが、typedef名や列挙定数とは衝突する
+                    if !self.globals.contains_key(&ident) {
+                        self.declare(&ident)?;
+                    }
+                    let gvar = self.gvar(storage.as_deref(), d, ident)?;
+                    if !gvar_order.contains(&gvar.ident) {
+                        gvar_order.push(gvar.ident);
+                    }
+                }
+                first = false;
+                if self.consume(",").is_some() {
+                    continue;
+                }
+                if self.consume(";").is_none() {
+                    return Err(self.fail("; expected".into()));
+                }
+                break;
+            }
         }
-        Ok(Program { fdefs: fdefs })
+        let gvars = gvar_order
+            .iter()
+            .map(|ident| self.globals[ident].clone())
+            .chain(self.statics.drain(..))
+            .collect();
+        Ok(Program { fdefs, gvars, asms })
     }
     fn parse(&mut self) -> ParseResult<Program> {
         self.program()
@@ -895,13 +2930,24 @@ impl Parser<'_> {
 }
 pub fn parse(input: &String) -> ParseResult<Program> {
     Parser {
-        input: input,
+        input,
         index: 0,
         required_memory: 0,
         line_index: 0,
         funcs: HashMap::new(),
         idents: HashMap::new(),
+        globals: HashMap::new(),
+        tags: HashMap::new(),
+        tag_scope: HashSet::new(),
+        enum_bodies: HashSet::new(),
+        enums: HashMap::new(),
+        typedefs: HashMap::new(),
+        scope: HashSet::new(),
         read_lines: 0,
+        labels: HashSet::new(),
+        gotos: Vec::new(),
+        func_linkages: HashMap::new(),
+        statics: Vec::new(),
     }
     .parse()
 }
@@ -1,42 +1,358 @@
+use std::{cell::RefCell, fmt, rc::Rc};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Member {
+    pub name: String,
+    pub type_: Type,
+    pub offset: usize,
+}
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructBody {
+    pub members: Vec<Member>,
+    pub size: usize,
+    pub align: usize,
+}
+// 構造体の型は宣言ごとに1つ作り、同じタグの参照はこれを共有する。
+// 自己参照する構造体のために、メンバは定義を読み終えてから埋める
+pub struct StructDef {
+    pub tag: String,
+    pub is_union: bool, // 共用体ならすべてのメンバがオフセット0を共有する
+    pub body: RefCell<Option<StructBody>>, // 不完全型ならNone
+}
+impl StructDef {
+    pub fn new(tag: String, is_union: bool) -> Self {
+        StructDef {
+            tag,
+            is_union,
+            body: RefCell::new(None),
+        }
+    }
+    pub fn keyword(&self) -> &str {
+        if self.is_union { "union" } else { "struct" }
+    }
+    pub fn member(&self, name: &str) -> Option<Member> {
+        let body = self.body.borrow();
+        body.as_ref()?
+            .members
+            .iter()
+            .find(|m| m.name == name)
+            .cloned()
+    }
+    pub fn is_complete(&self) -> bool {
+        self.body.borrow().is_some()
+    }
+}
+// 別々に宣言された構造体は、メンバが同じでも別の型になる
+impl PartialEq for StructDef {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+// 自己参照する構造体を辿って無限に出力しないよう、タグだけを出す
+impl fmt::Debug for StructDef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.keyword(), self.tag)
+    }
+}
+// ビットフィールドの位置。メンバのオフセットから宣言した型の大きさだけ読んだ単位の中で、下位shiftビット目からwidthビット
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BitField {
+    pub shift: usize,
+    pub width: usize,
+}
+// const/volatileの修飾子
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Qualifiers {
+    pub is_const: bool,
+    pub is_volatile: bool,
+}
+impl Qualifiers {
+    pub fn is_empty(&self) -> bool {
+        !self.is_const && !self.is_volatile
+    }
+    pub fn merge(self, other: Qualifiers) -> Qualifiers {
+        Qualifiers {
+            is_const: self.is_const || other.is_const,
+            is_volatile: self.is_volatile || other.is_volatile,
+        }
+    }
+    // otherが持っていて自分に無い修飾子の名前
+    pub fn missing(&self, other: &Qualifiers) -> Vec<&'static str> {
+        let mut names = vec![];
+        if other.is_const && !self.is_const {
+            names.push("const");
+        }
+        if other.is_volatile && !self.is_volatile {
+            names.push("volatile");
+        }
+        names
+    }
+}
+pub fn align_to(n: usize, align: usize) -> usize {
+    n.div_ceil(align) * align
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Type {
     _Panic, // 開発用
-    LInt,
+    Char,
+    UChar,
+    Short,
+    UShort,
     Int,
+    UInt,
+    Long,
+    ULong,
+    LongLong,
+    ULongLong,
+    Float,
+    Double,
+    LongDouble, // x87の80bit拡張倍精度。大きさと境界は16byte
     Ptr(Box<Type>),
-    Array(Box<(Type, usize)>), // usizeは深さ
+    Array(Box<(Type, usize)>), // 要素の型と要素数。多次元配列は配列の配列になる
+    Vla(Box<(Type, usize)>), // 可変長配列。末端の要素の型と次元数を持ち、各次元の大きさは実行時に決まる
+    Struct(Rc<StructDef>),
+    Func(Box<(Type, Option<Vec<Type>>)>), // 戻り値の型と引数の型。()で宣言されていれば引数は検査しない
+    // 修飾された型。変数やメンバ、ポインタの指す先の型にだけ現れ、式の型からは外す。配列は要素の型を修飾する
+    Qual(Box<(Type, Qualifiers)>),
+    // ビットフィールドのメンバの型。宣言した型と位置を持ち、メンバと初期化子の要素にだけ現れる
+    Bits(Box<(Type, BitField)>),
 }
 impl Type {
     pub fn when_addsub(&self, register: String) -> Vec<String> {
         match &self {
-            Type::LInt | Type::Int => vec![],
-            Type::Array(_) => {
-                vec![format!("imul {}, {}", register, self.sizeof_item())]
-            }
-            Type::Ptr(t) => vec![format!("imul {}, {}", register, t.sizeof_item())],
+            t if t.is_integer() => vec![],
+            Type::Vla(_) => vec![format!("imul {}, {}", register, self.sizeof_item())],
+            Type::Ptr(t) => vec![format!("imul {}, {}", register, t.sizeof())],
             _ => vec![],
         }
     }
     pub fn sizeof(&self) -> usize {
         match self {
             Type::_Panic => panic!("type Panic found"),
-            Type::Int => 4, // 適切なレジスタを選択できていないので8固定
+            Type::Char | Type::UChar => 1,
+            Type::Short | Type::UShort => 2,
+            Type::Int | Type::UInt => 4,
+            Type::Long | Type::ULong | Type::LongLong | Type::ULongLong => 8,
+            Type::Float => 4,
+            Type::Double => 8,
+            Type::LongDouble => 16,
             Type::Ptr(_) => 8,
-            Type::LInt => 4, // 数値で中身が不明ならIntとみなす
-            Type::Array(_) => 8,
+            Type::Array(t) => t.0.sizeof() * t.1,
+            Type::Vla(_) => 8, // 実体へのポインタを置くスロットの大きさ
+            Type::Struct(s) => s.body.borrow().as_ref().map_or(0, |b| b.size),
+            Type::Func(_) => 1, // gccに倣う
+            Type::Qual(t) => t.0.sizeof(),
+            Type::Bits(b) => b.0.sizeof(), // ビットフィールドを読み書きする単位の大きさ
+        }
+    }
+    pub fn alignof(&self) -> usize {
+        match self {
+            Type::Qual(t) => t.0.alignof(),
+            Type::Bits(b) => b.0.alignof(),
+            Type::Array(t) => t.0.alignof(),
+            Type::Vla(t) => t.0.alignof(),
+            Type::Struct(s) => s.body.borrow().as_ref().map_or(1, |b| b.align),
+            t => t.sizeof(),
         }
     }
     pub fn sizeof_item(&self) -> usize {
         match self {
             Type::Array(t) => t.0.sizeof_item(),
+            Type::Vla(t) => t.0.sizeof(),
             Type::Ptr(t) => t.sizeof(), // TODO 正しい？
+            Type::Qual(t) => t.0.sizeof_item(),
             t => t.sizeof(),
         }
     }
-    pub fn can_be_for_array_index(&self) -> bool {
+    // 修飾子を付けた型。配列なら要素の型に付ける
+    pub fn qualify(&self, q: Qualifiers) -> Type {
+        match self {
+            _ if q.is_empty() => self.clone(),
+            Type::Array(t) => Type::Array(Box::new((t.0.qualify(q), t.1))),
+            Type::Vla(t) => Type::Vla(Box::new((t.0.qualify(q), t.1))),
+            Type::Qual(t) => Type::Qual(Box::new((t.0.clone(), t.1.merge(q)))),
+            Type::Bits(b) => Type::Bits(Box::new((b.0.qualify(q), b.1))),
+            t => Type::Qual(Box::new((t.clone(), q))),
+        }
+    }
+    // 修飾子を外した型
+    pub fn base(&self) -> &Type {
+        match self {
+            Type::Qual(t) => &t.0,
+            t => t,
+        }
+    }
+    pub fn unqualified(&self) -> Type {
+        match self {
+            Type::Bits(b) => Type::Bits(Box::new((b.0.unqualified(), b.1))),
+            t => t.base().clone(),
+        }
+    }
+    // 配列は要素の型の修飾子を持つ
+    pub fn qualifiers(&self) -> Qualifiers {
         match self {
-            Type::Int | Type::LInt => true,
+            Type::Qual(t) => t.1,
+            Type::Array(t) => t.0.qualifiers(),
+            Type::Vla(t) => t.0.qualifiers(),
+            Type::Bits(b) => b.0.qualifiers(),
+            _ => Qualifiers::default(),
+        }
+    }
+    // 代入で書き換えられない型。constなメンバを持つ構造体も丸ごとは書き換えられない
+    pub fn is_read_only(&self) -> bool {
+        if self.qualifiers().is_const {
+            return true;
+        }
+        match self.base() {
+            Type::Struct(s) => s
+                .body
+                .borrow()
+                .as_ref()
+                .is_some_and(|b| b.members.iter().any(|m| m.type_.is_read_only())),
             _ => false,
         }
     }
+    // 間接参照や添字アクセスで得られる型。値として使うので修飾子は外す
+    pub fn deref(&self) -> Option<Type> {
+        self.deref_qualified().map(|t| t.unqualified())
+    }
+    // 間接参照や添字アクセスで指す先のオブジェクトの型
+    pub fn deref_qualified(&self) -> Option<Type> {
+        match self.base() {
+            Type::Ptr(t) => Some(*t.clone()),
+            Type::Array(t) => Some(t.0.clone()),
+            Type::Vla(t) if t.1 > 1 => Some(Type::Vla(Box::new((t.0.clone(), t.1 - 1)))),
+            Type::Vla(t) => Some(t.0.clone()),
+            Type::Func(_) => Some(self.clone()), // *fは関数そのもの
+            _ => None,
+        }
+    }
+    // 呼び出せる型なら関数の型を返す。関数へのポインタも呼び出せる
+    pub fn callee(&self) -> Option<&(Type, Option<Vec<Type>>)> {
+        match self {
+            Type::Func(f) => Some(f),
+            Type::Ptr(t) => match t.base() {
+                Type::Func(f) => Some(f),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+    // 式の中で配列は先頭要素へのポインタとして扱われる
+    pub fn decay(&self) -> Type {
+        match self {
+            Type::Array(t) => Type::Ptr(Box::new(t.0.clone())),
+            Type::Vla(t) if t.1 == 1 => Type::Ptr(Box::new(t.0.clone())),
+            Type::Func(_) => Type::Ptr(Box::new(self.clone())),
+            t => t.clone(),
+        }
+    }
+    pub fn is_pointer(&self) -> bool {
+        matches!(self.base(), Type::Ptr(_))
+    }
+    pub fn is_integer(&self) -> bool {
+        matches!(
+            self.base(),
+            Type::Char
+                | Type::UChar
+                | Type::Short
+                | Type::UShort
+                | Type::Int
+                | Type::UInt
+                | Type::Long
+                | Type::ULong
+                | Type::LongLong
+                | Type::ULongLong
+        )
+    }
+    pub fn is_float(&self) -> bool {
+        matches!(self.base(), Type::Float | Type::Double | Type::LongDouble)
+    }
+    pub fn is_arithmetic(&self) -> bool {
+        self.is_integer() || self.is_float()
+    }
+    pub fn is_signed(&self) -> bool {
+        matches!(
+            self.base(),
+            Type::Char | Type::Short | Type::Int | Type::Long | Type::LongLong
+        )
+    }
+    // 整数拡張。intより小さい型は演算の前にintになる
+    pub fn promote(&self) -> Type {
+        match self {
+            Type::Char | Type::UChar | Type::Short | Type::UShort => Type::Int,
+            t => t.clone(),
+        }
+    }
+    fn rank(&self) -> usize {
+        match self {
+            Type::Long | Type::ULong => 2,
+            Type::LongLong | Type::ULongLong => 3,
+            _ => 1,
+        }
+    }
+    fn to_unsigned(&self) -> Type {
+        match self {
+            Type::Int => Type::UInt,
+            Type::Long => Type::ULong,
+            Type::LongLong => Type::ULongLong,
+            t => t.clone(),
+        }
+    }
+    // 通常の算術型変換で両辺を揃える型。ポインタはアドレスとして符号なしで扱う
+    pub fn common(&self, other: &Type) -> Type {
+        if !self.is_arithmetic() || !other.is_arithmetic() {
+            return Type::ULong;
+        }
+        for t in [Type::LongDouble, Type::Double, Type::Float] {
+            if *self == t || *other == t {
+                return t;
+            }
+        }
+        let (a, b) = (self.promote(), other.promote());
+        if a == b {
+            return a;
+        }
+        if a.is_signed() == b.is_signed() {
+            return if a.rank() >= b.rank() { a } else { b };
+        }
+        let (s, u) = if a.is_signed() { (a, b) } else { (b, a) };
+        if u.rank() >= s.rank() {
+            u
+        } else if s.sizeof() > u.sizeof() {
+            s
+        } else {
+            s.to_unsigned()
+        }
+    }
+    // 整数をこの型で表せる値に変換する。上位のビットを捨て、符号付きなら符号拡張する
+    pub fn convert(&self, v: i64) -> i64 {
+        match self.base() {
+            Type::Char => v as i8 as i64,
+            Type::UChar => v as u8 as i64,
+            Type::Short => v as i16 as i64,
+            Type::UShort => v as u16 as i64,
+            Type::Int => v as i32 as i64,
+            Type::UInt => v as u32 as i64,
+            _ => v,
+        }
+    }
+    // 整数型が表現できる最大値
+    pub fn max_value(&self) -> u64 {
+        match self.base() {
+            Type::Char => i8::MAX as u64,
+            Type::UChar => u8::MAX as u64,
+            Type::Short => i16::MAX as u64,
+            Type::UShort => u16::MAX as u64,
+            Type::Int => i32::MAX as u64,
+            Type::UInt => u32::MAX as u64,
+            Type::Long | Type::LongLong => i64::MAX as u64,
+            Type::ULong | Type::ULongLong => u64::MAX,
+            _ => 0,
+        }
+    }
+    pub fn can_be_for_array_index(&self) -> bool {
+        self.is_integer()
+    }
 }
@@ -1,20 +1,27 @@
 use std::env;
 mod compiler;
+use compiler::compiler::Options;
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut opts = Options::default();
+    let mut sources = vec![];
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--bounds-check" => opts.bounds_check = true,
+            "--warn-uninitialized" => opts.warn_uninitialized = true,
+            _ => sources.push(arg),
+        }
+    }
 
-    if args.len() < 2 {
+    if sources.is_empty() {
         println!("no arg given");
         return;
     }
-    let raw_stmt = args[1].clone();
+    let raw_stmt = sources[0].clone();
 
     println!(
         ".intel_syntax noprefix
-.globl main
 {}
 ",
-        compiler::compiler::compile(raw_stmt).join("\n")
+        compiler::compiler::compile(raw_stmt, &opts).join("\n")
     );
-    return;
 }
@@ -13,15 +13,19 @@ assert() {
   cnt=$((cnt+1))
   input=$1
   expect=$2
+  flags=$3 # コンパイラに渡す追加のフラグ
+  warning=$4 # 4つ目の引数があれば、コンパイラの標準エラー出力と比べる
   if [ -z "$input" ] || [ -z "$expect" ]; then
     echo "not enough argument"
     return
   fi
   file_name="output_$cnt"
-  RUSTFLAGS="-Awarnings" cargo run -q -- "$input" > $tmp/$file_name.s
+  RUSTFLAGS="-Awarnings" cargo run -q -- $flags "$input" > $tmp/$file_name.s 2> $tmp/$file_name.err
   cc -z noexecstack -o $tmp/$file_name $utils $tmp/$file_name.s
   actual="$($tmp/$file_name)"
-  if [ "$actual" = "$expect" ]; then
+  if [ $# -ge 4 ] && [ "$(cat $tmp/$file_name.err)" != "$warning" ]; then
+    echo "($cnt) ❌ want warning: $warning real: $(cat $tmp/$file_name.err)/ $input"
+  elif [ "$actual" = "$expect" ]; then
     echo "($cnt) ✅️ $actual/ $input"
   else
     echo "($cnt) ❌ want: $expect real: $actual/ $input"
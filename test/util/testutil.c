@@ -4,4 +4,35 @@ int _p(int v) {
   if (cnt++) printf("%c",',');
   printf("%d", v);
   return v;
-}
\ No newline at end of file
+}
+// 構造体の値渡しがgccと互換か確かめる
+struct _s1 {char c; int i;};
+struct _s2 {double d; int i; float f;};
+struct _s3 {float a, b, c;};
+struct _s4 {long a, b, c;};
+struct _s1 _s1_of(char c, int i) { struct _s1 s = {c, i}; return s; }
+struct _s2 _s2_of(double d, int i, float f) { struct _s2 s = {d, i, f}; return s; }
+struct _s3 _s3_of(float a, float b, float c) { struct _s3 s = {a, b, c}; return s; }
+struct _s4 _s4_of(long a, long b, long c) { struct _s4 s = {a, b, c}; return s; }
+long _s_sum(struct _s1 a, struct _s2 b, struct _s3 c, struct _s4 d) {
+  return a.c + a.i + (long)(b.d * 10) + b.i + (long)(b.f * 10) + (long)(c.a + c.b + c.c) + d.a + d.b + d.c;
+}
+long _s_many(struct _s2 a, struct _s2 b, struct _s2 c, struct _s2 d, struct _s2 e, struct _s2 f, struct _s2 g, struct _s3 h) {
+  return a.i + b.i + c.i + d.i + e.i + f.i + (long)(g.d * 10) + g.i + (long)(g.f * 10) + (long)(h.a + h.b + h.c);
+}
+long _s_apply(struct _s4 (*f)(struct _s1, struct _s2, struct _s3, struct _s4)) {
+  struct _s4 r = f(_s1_of(1, 2), _s2_of(3.5, 4, 5.5), _s3_of(6, 7, 8), _s4_of(9, 10, 11));
+  return r.a * 10000 + r.b * 100 + r.c;
+}
+// long doubleだけの構造体は引数ではメモリ経由、戻り値はst0で返る
+struct _lds {long double x;};
+struct _lds _lds_of(long v) {
+  struct _lds r = {v};
+  return r;
+}
+long _lds_get(struct _lds s) {
+  return s.x * 2;
+}
+long _lds_apply(struct _lds (*f)(long)) {
+  return f(21).x + 1;
+}